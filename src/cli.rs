@@ -0,0 +1,277 @@
+use clap::{Args, Parser, Subcommand};
+
+use crate::config::Config;
+use crate::encoder::Format;
+use crate::recipe::{ParseOptions, StepSelector, WindowBound};
+use crate::stage::filter::{default_q, Filter};
+use crate::stage::rhythm::Thresholds;
+
+/// Parses CARNE1.0 recordings. Without a subcommand, parses the recordings
+/// named in `config.json`, or the file `CONFIG_DIR` points to.
+#[derive(Parser, Debug)]
+#[command(name = "taqueria", version)]
+pub struct Cli {
+    /// Accept files whose header checksum does not match or is missing.
+    #[arg(long, global = true)]
+    pub lenient: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Decode recordings into an output directory.
+    Parse(Box<ParseArgs>),
+    /// Print the header of a recording and its duration without decoding
+    /// its samples.
+    Inspect {
+        file: String,
+        /// Print JSON instead of text.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check the magic number, header checksum and file size of a recording.
+    Validate { file: String },
+    /// List the registered recipes.
+    ListRecipes,
+}
+
+#[derive(Args, Debug)]
+pub struct ParseArgs {
+    /// A recording, or a directory, glob or `@manifest` of several.
+    pub file: String,
+    /// Directory each recording's output directory is created in.
+    #[arg(long, value_name = "DIR")]
+    pub out: Option<String>,
+    /// One of json, csv, npy, npy32, binary, edf or wfdb.
+    #[arg(long)]
+    pub format: Option<Format>,
+    /// Keep the per-chunk files once they have been merged.
+    #[arg(long)]
+    pub keep_intermediates: bool,
+    /// Recordings parsed at the same time.
+    #[arg(long)]
+    pub concurrency: Option<usize>,
+    /// First sample to parse, as an index or a time like 2008-11-05T05:15:00.
+    #[arg(long)]
+    pub start: Option<WindowBound>,
+    /// Sample to stop before, as an index or a time.
+    #[arg(long)]
+    pub end: Option<WindowBound>,
+    /// Steps to parse, by name or index, as in `--steps II,V1`.
+    #[arg(long, value_delimiter = ',')]
+    pub steps: Vec<StepSelector>,
+    /// Resample every step to this rate, in Hz.
+    #[arg(long, value_name = "HZ")]
+    pub resample: Option<u16>,
+    /// Remove baseline wander below this frequency, in Hz, with a zero-phase
+    /// high-pass filter.
+    #[arg(long, value_name = "HZ")]
+    pub high_pass: Option<f64>,
+    /// Remove mains hum at this frequency, in Hz, usually 50 or 60.
+    #[arg(long, value_name = "HZ")]
+    pub notch: Option<f64>,
+    /// Quality factor of the notch: its frequency over its width.
+    #[arg(long, default_value_t = default_q(), requires = "notch")]
+    pub notch_q: f64,
+    /// Multiples of the notch frequency removed as well.
+    #[arg(long, default_value_t = 0, requires = "notch")]
+    pub notch_harmonics: u32,
+    /// Append III, aVR, aVL and aVF computed from leads I and II.
+    #[arg(long)]
+    pub derive_leads: bool,
+    /// Find R peaks on this step, by name or index, and write `beats.json`.
+    #[arg(long, value_name = "STEP")]
+    pub beats: Option<StepSelector>,
+    /// Write the heart-rate trend and rate episodes of the beats to
+    /// `rhythm.json`.
+    #[arg(long, requires = "beats")]
+    pub rhythm: bool,
+    /// Report stretches of the beats slower than this rate, in bpm.
+    #[arg(long, value_name = "BPM", requires = "rhythm")]
+    pub bradycardia: Option<f64>,
+    /// Report stretches of the beats faster than this rate, in bpm.
+    #[arg(long, value_name = "BPM", requires = "rhythm")]
+    pub tachycardia: Option<f64>,
+    /// Shortest stretch, in seconds, reported as an episode.
+    #[arg(long, value_name = "SECONDS", requires = "rhythm")]
+    pub min_episode: Option<f64>,
+}
+
+impl ParseArgs {
+    /// Filters asked for on the command line, in the order they run.
+    #[must_use]
+    pub fn filters(&self) -> Vec<Filter> {
+        let notch = self.notch.map(|frequency| Filter::Notch {
+            frequency,
+            q: self.notch_q,
+            harmonics: self.notch_harmonics,
+        });
+        self.high_pass
+            .map(|cutoff| Filter::HighPass { cutoff })
+            .into_iter()
+            .chain(notch)
+            .collect()
+    }
+
+    /// Thresholds of the rhythm report, those given on the command line over
+    /// those of `config`.
+    #[must_use]
+    pub fn rhythm(&self, config: Option<Thresholds>) -> Option<Thresholds> {
+        if !self.rhythm {
+            return config;
+        }
+        let config = config.unwrap_or_default();
+        Some(Thresholds {
+            bradycardia: self.bradycardia.unwrap_or(config.bradycardia),
+            tachycardia: self.tachycardia.unwrap_or(config.tachycardia),
+            min_seconds: self.min_episode.unwrap_or(config.min_seconds),
+        })
+    }
+
+    /// `config` with every flag given on the command line taking precedence.
+    #[must_use]
+    pub fn apply(&self, config: Config) -> Config {
+        let options = config.options;
+        Config {
+            filepath: self.file.clone(),
+            basepath: self.out.clone().unwrap_or(config.basepath),
+            concurrency: self.concurrency.unwrap_or(config.concurrency),
+            options: ParseOptions {
+                format: self.format.unwrap_or(options.format),
+                keep_intermediates: self.keep_intermediates || options.keep_intermediates,
+                derive_leads: self.derive_leads || options.derive_leads,
+                beats: self.beats.clone().or(options.beats),
+                rhythm: self.rhythm(options.rhythm),
+                start: self.start.or(options.start),
+                end: self.end.or(options.end),
+                resample: self.resample.or(options.resample),
+                steps: if self.steps.is_empty() {
+                    options.steps
+                } else {
+                    self.steps.clone()
+                },
+                filters: if self.filters().is_empty() {
+                    options.filters
+                } else {
+                    self.filters()
+                },
+                ..options
+            },
+            ..config
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_given_parse_flags_when_apply_then_flags_override_config() {
+        let cli = Cli::try_parse_from([
+            "taqueria",
+            "parse",
+            "holter.dat",
+            "--out",
+            "/tmp/out",
+            "--format",
+            "csv",
+            "--lenient",
+            "--start",
+            "2008-11-05T05:15:00",
+            "--steps",
+            "II,0",
+            "--high-pass",
+            "0.5",
+            "--notch",
+            "60",
+            "--notch-harmonics",
+            "2",
+            "--resample",
+            "250",
+            "--beats",
+            "II",
+            "--rhythm",
+            "--tachycardia",
+            "120",
+        ])
+        .unwrap();
+        assert!(cli.lenient);
+        let Some(Command::Parse(args)) = cli.command else {
+            panic!("expected parse");
+        };
+        let config = args.apply(Config {
+            basepath: String::from("."),
+            filepath: String::from("config.dat"),
+            concurrency: 4,
+            options: ParseOptions {
+                format: Format::Npy,
+                ..ParseOptions::default()
+            },
+            ..Config::default()
+        });
+        assert_eq!(config.filepath, "holter.dat");
+        assert_eq!(config.basepath, "/tmp/out");
+        assert_eq!(config.options.format, Format::Csv);
+        assert_eq!(config.concurrency, 4);
+        assert_eq!(
+            config.options.start,
+            Some(WindowBound::Time(
+                chrono::NaiveDate::from_ymd_opt(2008, 11, 5)
+                    .unwrap()
+                    .and_hms_opt(5, 15, 0)
+                    .unwrap()
+            ))
+        );
+        assert_eq!(config.options.end, None);
+        assert_eq!(config.options.resample, Some(250));
+        assert_eq!(
+            config.options.beats,
+            Some(StepSelector::Name(String::from("II")))
+        );
+        assert_eq!(
+            config.options.rhythm,
+            Some(Thresholds {
+                tachycardia: 120.0,
+                ..Thresholds::default()
+            })
+        );
+        assert_eq!(
+            config.options.filters,
+            [
+                Filter::HighPass { cutoff: 0.5 },
+                Filter::Notch {
+                    frequency: 60.0,
+                    q: 30.0,
+                    harmonics: 2
+                }
+            ]
+        );
+        assert_eq!(
+            config.options.steps,
+            [
+                StepSelector::Name(String::from("II")),
+                StepSelector::Index(0)
+            ]
+        );
+        assert!(!config.options.keep_intermediates);
+    }
+
+    #[test]
+    fn test_given_unknown_format_when_parse_then_error() {
+        assert!(Cli::try_parse_from(["taqueria", "parse", "a.dat", "--format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn test_given_no_subcommand_when_parse_then_config_driven() {
+        let cli = Cli::try_parse_from(["taqueria"]).unwrap();
+        assert!(cli.command.is_none());
+        assert!(matches!(
+            Cli::try_parse_from(["taqueria", "list-recipes"])
+                .unwrap()
+                .command,
+            Some(Command::ListRecipes)
+        ));
+    }
+}
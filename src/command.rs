@@ -17,7 +17,12 @@ impl TryFrom<String> for Command<serde_json::Value> {
 }
 
 pub trait CommandHandler<T, R> {
-    fn handle(&self, command: &Command<T>) -> Option<Event<R>>;
+    type Error;
+
+    /// # Errors
+    ///
+    /// Returns `Self::Error` when the command could not be handled.
+    fn handle(&self, command: &Command<T>) -> Result<Event<R>, Self::Error>;
 }
 
 #[cfg(test)]
@@ -7,8 +7,9 @@ const CONFIG_DIR: &str = "CONFIG_DIR";
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct ConfigRecipeError;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub enum Environment {
+    #[default]
     Local,
 }
 
@@ -16,7 +17,32 @@ pub enum Environment {
 pub struct Config {
     pub environment: Environment,
     pub basepath: String,
+    /// A recording, or a directory, glob or `@manifest` of several.
     pub filepath: String,
+    /// Recordings parsed at the same time when `filepath` names several.
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// How each recording is parsed, as fields of the configuration itself.
+    #[serde(flatten)]
+    pub options: crate::recipe::ParseOptions,
+}
+
+fn default_concurrency() -> usize {
+    1
+}
+
+/// What the command line starts from when there is no configuration file:
+/// output next to the working directory, one file at a time.
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            environment: Environment::default(),
+            basepath: String::from("."),
+            filepath: String::new(),
+            concurrency: default_concurrency(),
+            options: crate::recipe::ParseOptions::default(),
+        }
+    }
 }
 
 impl Config {
@@ -27,17 +53,15 @@ impl Config {
 
     #[must_use]
     pub fn path() -> String {
-        env::var(CONFIG_DIR).map_or_else(
-            |_| {
-                env::current_dir()
-                    .expect("Could not get `CONFIG_DIR` or current directory.")
-                    .join(String::from("config.json"))
-                    .to_str()
-                    .expect("Could not get `CONFIG_DIR` to current directory.")
-                    .into()
-            },
-            |path| path,
-        )
+        match env::var(CONFIG_DIR) {
+            Ok(path) => path,
+            Err(_) => env::current_dir()
+                .expect("Could not get `CONFIG_DIR` or current directory.")
+                .join(String::from("config.json"))
+                .to_str()
+                .expect("Could not get `CONFIG_DIR` to current directory.")
+                .into(),
+        }
     }
 }
 
@@ -88,6 +112,50 @@ mod tests {
         assert!(matches!(conf.environment, Environment::Local));
     }
 
+    #[test]
+    fn test_given_config_without_options_when_read_then_defaults_applied() {
+        let json = r#"
+        {
+            "environment": "Local",
+            "basepath": ".",
+            "filepath": "./assets/carne_asada.dat"
+        }"#;
+        let mut tmpfile = NamedTempFile::new().unwrap();
+        write!(tmpfile, "{json}").unwrap();
+        let path = tmpfile.path().as_os_str().to_str().expect("not found");
+        let conf = Config::read(String::from(path)).unwrap();
+        assert!(!conf.options.lenient);
+        assert_eq!(conf.concurrency, 1);
+    }
+
+    #[test]
+    fn test_given_parse_options_when_read_then_options_initialized() {
+        let json = r#"
+        {
+            "environment": "Local",
+            "basepath": ".",
+            "filepath": "./assets/carne_asada.dat",
+            "lenient": true,
+            "format": "csv",
+            "steps": ["II", 0],
+            "derive_leads": true
+        }"#;
+        let mut tmpfile = NamedTempFile::new().unwrap();
+        write!(tmpfile, "{json}").unwrap();
+        let path = tmpfile.path().as_os_str().to_str().expect("not found");
+        let options = Config::read(String::from(path)).unwrap().options;
+        assert!(options.lenient && options.derive_leads);
+        assert_eq!(options.format, crate::encoder::Format::Csv);
+        assert_eq!(
+            options.steps,
+            [
+                crate::recipe::StepSelector::Name(String::from("II")),
+                crate::recipe::StepSelector::Index(0)
+            ]
+        );
+        assert_eq!(options.beats, None);
+    }
+
     #[test]
     fn test_given_no_env_var_when_path_then_current_directory() {
         remove_var(CONFIG_DIR);
@@ -0,0 +1,158 @@
+pub mod binary;
+pub mod csv;
+pub mod edf;
+pub mod json;
+pub mod npy;
+pub mod wfdb;
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::Metadata;
+use crate::recipe::RecipeError;
+
+const PARTIAL_EXTENSION: &str = ".partial";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+    Npy,
+    Npy32,
+    Binary,
+    Edf,
+    Wfdb,
+}
+
+impl Format {
+    #[must_use]
+    pub fn encoder(self, dir: &Path, keep_intermediates: bool) -> Box<dyn OutputEncoder> {
+        match self {
+            Self::Json => Box::new(json::JsonEncoder::new(dir, keep_intermediates)),
+            Self::Csv => Box::new(csv::CsvEncoder::new(dir)),
+            Self::Npy => Box::new(npy::NpyEncoder::new(dir, npy::Dtype::F64)),
+            Self::Npy32 => Box::new(npy::NpyEncoder::new(dir, npy::Dtype::F32)),
+            Self::Binary => Box::new(binary::BinaryEncoder::new(dir)),
+            Self::Edf => Box::new(edf::EdfEncoder::new(dir)),
+            Self::Wfdb => Box::new(wfdb::WfdbEncoder::new(dir)),
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "npy" => Ok(Self::Npy),
+            "npy32" => Ok(Self::Npy32),
+            "binary" => Ok(Self::Binary),
+            "edf" => Ok(Self::Edf),
+            "wfdb" => Ok(Self::Wfdb),
+            other => Err(format!("unknown format `{other}`")),
+        }
+    }
+}
+
+/// Writes decoded guacamole, one block of consecutive samples at a time, in
+/// a particular output format.
+pub trait OutputEncoder {
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::OutputWrite`] when the output cannot be created.
+    fn begin(&mut self, metadata: &Metadata) -> Result<(), RecipeError>;
+
+    /// Encodes `guacamole`, one vector per step, whose first sample is sample
+    /// `onset` of the output, counted from [`Metadata::first_sample`] of the
+    /// recording. Blocks arrive in order.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::OutputWrite`] when the block cannot be written.
+    fn encode(&mut self, onset: u64, guacamole: &[Vec<f64>]) -> Result<(), RecipeError>;
+
+    /// Whether the encoder stores the recorder's interleaved 16-bit samples
+    /// as they are, in which case blocks are handed to
+    /// [`OutputEncoder::encode_raw`] without being decoded.
+    fn accepts_raw(&self) -> bool {
+        false
+    }
+
+    /// Encodes a block of raw interleaved little-endian samples whose first
+    /// frame is sample `onset` of the output. Only called when
+    /// [`OutputEncoder::accepts_raw`] is true.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::OutputWrite`] when the block cannot be written.
+    fn encode_raw(&mut self, onset: u64, raw: &[u8]) -> Result<(), RecipeError> {
+        let _ = (onset, raw);
+        Ok(())
+    }
+
+    /// Completes the output and moves it into place, returning its path.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::OutputWrite`] when the output cannot be completed.
+    fn finish(&mut self) -> Result<PathBuf, RecipeError>;
+}
+
+pub(crate) fn output_error(path: &Path) -> impl FnOnce(io::Error) -> RecipeError + '_ {
+    move |source| RecipeError::OutputWrite {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// Name under which an output is written until it is complete.
+pub(crate) fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_os_string();
+    partial.push(PARTIAL_EXTENSION);
+    PathBuf::from(partial)
+}
+
+/// Moves a completed output from its partial name into place.
+pub(crate) fn commit(path: &Path) -> Result<PathBuf, RecipeError> {
+    std::fs::rename(partial_path(path), path).map_err(output_error(path))?;
+    Ok(path.to_path_buf())
+}
+
+/// Millivolts per least significant bit for each step, from the recorder's
+/// resolution in nanovolts.
+pub(crate) fn step_scales(metadata: &Metadata) -> Vec<f64> {
+    (0..usize::from(metadata.number_of_steps))
+        .map(|i| {
+            let resolution = metadata.unit_conversion.get(i).copied().unwrap_or(1000);
+            f64::from(if resolution == 0 { 1 } else { resolution }) * 10_f64.powi(-6)
+        })
+        .collect()
+}
+
+/// Converts a value in mV back to the recorder's 16-bit digital units.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn quantize(value: f64, scale: f64) -> i16 {
+    (value / scale)
+        .round()
+        .clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16
+}
+
+/// Column names for each step, falling back to the step index for steps the
+/// header does not name.
+pub(crate) fn step_labels(metadata: &Metadata) -> Vec<String> {
+    (0..usize::from(metadata.number_of_steps))
+        .map(|i| {
+            metadata
+                .steps
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| format!("step_{i}"))
+        })
+        .collect()
+}
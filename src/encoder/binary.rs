@@ -0,0 +1,135 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::metadata::Metadata;
+use crate::recipe::RecipeError;
+
+use super::{commit, output_error, partial_path, step_labels, OutputEncoder};
+
+pub const FILENAME: &str = "recipe.bin";
+pub const SIDECAR_FILENAME: &str = "recipe.bin.json";
+
+/// Layout of [`FILENAME`], stored next to it in [`SIDECAR_FILENAME`].
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    dtype: &'static str,
+    order: &'static str,
+    shape: [u64; 2],
+    columns: Vec<String>,
+    metadata: &'a Metadata,
+}
+
+/// Writes samples as interleaved little-endian `f32`, one frame of steps per
+/// sample, with a JSON sidecar describing the layout.
+pub struct BinaryEncoder {
+    path: PathBuf,
+    sidecar_path: PathBuf,
+    rows: u64,
+    metadata: Option<Metadata>,
+    writer: Option<BufWriter<File>>,
+}
+
+impl BinaryEncoder {
+    #[must_use]
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(FILENAME),
+            sidecar_path: dir.join(SIDECAR_FILENAME),
+            rows: 0,
+            metadata: None,
+            writer: None,
+        }
+    }
+}
+
+impl OutputEncoder for BinaryEncoder {
+    fn begin(&mut self, metadata: &Metadata) -> Result<(), RecipeError> {
+        let file = File::create(partial_path(&self.path)).map_err(output_error(&self.path))?;
+        self.writer = Some(BufWriter::new(file));
+        self.metadata = Some(metadata.clone());
+        Ok(())
+    }
+
+    fn encode(&mut self, _onset: u64, guacamole: &[Vec<f64>]) -> Result<(), RecipeError> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        let samples = guacamole.first().map_or(0, Vec::len);
+        for i in 0..samples {
+            for guac in guacamole {
+                #[allow(clippy::cast_possible_truncation)]
+                writer
+                    .write_all(&(guac[i] as f32).to_le_bytes())
+                    .map_err(output_error(&self.path))?;
+            }
+        }
+        self.rows += samples as u64;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<PathBuf, RecipeError> {
+        if let Some(mut writer) = self.writer.take() {
+            writer
+                .flush()
+                .and_then(|()| writer.get_ref().sync_all())
+                .map_err(output_error(&self.path))?;
+        }
+        if let Some(metadata) = self.metadata.take() {
+            let columns = step_labels(&metadata);
+            let sidecar = Sidecar {
+                dtype: "<f4",
+                order: "interleaved",
+                shape: [self.rows, columns.len() as u64],
+                columns,
+                metadata: &metadata,
+            };
+            let file = File::create(partial_path(&self.sidecar_path))
+                .map_err(output_error(&self.sidecar_path))?;
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer(&mut writer, &sidecar)
+                .map_err(io::Error::from)
+                .and_then(|()| writer.flush())
+                .map_err(output_error(&self.sidecar_path))?;
+            commit(&self.sidecar_path)?;
+        }
+        commit(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_given_blocks_when_encode_then_interleaved_f32_and_sidecar() {
+        let dir = tempdir().unwrap();
+        let mut encoder = BinaryEncoder::new(dir.path());
+        encoder
+            .begin(&Metadata {
+                number_of_steps: 2,
+                steps: vec![String::from("I"), String::from("II")],
+                ..Metadata::default()
+            })
+            .unwrap();
+        encoder
+            .encode(0, &[vec![1.0, 2.0], vec![-1.0, -2.0]])
+            .unwrap();
+        let bytes = std::fs::read(encoder.finish().unwrap()).unwrap();
+        let values: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, vec![1.0, -1.0, 2.0, -2.0]);
+        let sidecar: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join(SIDECAR_FILENAME)).unwrap())
+                .unwrap();
+        assert_eq!(sidecar["shape"], serde_json::json!([2, 2]));
+        assert_eq!(sidecar["columns"], serde_json::json!(["I", "II"]));
+        assert_eq!(sidecar["metadata"]["number_of_steps"], 2);
+    }
+}
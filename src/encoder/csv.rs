@@ -0,0 +1,132 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::metadata::Metadata;
+use crate::recipe::RecipeError;
+
+use super::{commit, output_error, partial_path, step_labels, OutputEncoder};
+
+pub const FILENAME: &str = "recipe.csv";
+const DELIMITER: &str = ",";
+
+/// Writes one row per sample: the time in seconds since the start of the
+/// extracted window followed by one column per step. Where the window starts
+/// in the recording is in the metadata, as `first_sample` and
+/// `date_of_recipe` and `time_of_recipe`.
+pub struct CsvEncoder {
+    path: PathBuf,
+    granularity: f64,
+    writer: Option<BufWriter<File>>,
+}
+
+impl CsvEncoder {
+    #[must_use]
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(FILENAME),
+            granularity: 1.0,
+            writer: None,
+        }
+    }
+}
+
+impl OutputEncoder for CsvEncoder {
+    fn begin(&mut self, metadata: &Metadata) -> Result<(), RecipeError> {
+        self.granularity = f64::from(metadata.granularity.max(1));
+        let partial = partial_path(&self.path);
+        let file = File::create(&partial).map_err(output_error(&self.path))?;
+        let mut writer = BufWriter::new(file);
+        let mut columns = vec![String::from("time")];
+        columns.extend(step_labels(metadata));
+        writeln!(writer, "{}", columns.join(DELIMITER)).map_err(output_error(&self.path))?;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    fn encode(&mut self, onset: u64, guacamole: &[Vec<f64>]) -> Result<(), RecipeError> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        let samples = guacamole.first().map_or(0, Vec::len);
+        for i in 0..samples {
+            #[allow(clippy::cast_precision_loss)]
+            let time = (onset + i as u64) as f64 / self.granularity;
+            write!(writer, "{time}").map_err(output_error(&self.path))?;
+            for guac in guacamole {
+                write!(writer, "{DELIMITER}{}", guac[i]).map_err(output_error(&self.path))?;
+            }
+            writeln!(writer).map_err(output_error(&self.path))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<PathBuf, RecipeError> {
+        if let Some(mut writer) = self.writer.take() {
+            writer
+                .flush()
+                .and_then(|()| writer.get_ref().sync_all())
+                .map_err(output_error(&self.path))?;
+        }
+        commit(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::{tempdir, NamedTempFile};
+
+    use super::*;
+    use crate::recipe::carne_asade::fixture;
+    use crate::recipe::WindowBound;
+
+    #[test]
+    fn test_given_blocks_when_encode_then_time_and_step_columns() {
+        let dir = tempdir().unwrap();
+        let mut encoder = CsvEncoder::new(dir.path());
+        encoder
+            .begin(&Metadata {
+                number_of_steps: 2,
+                steps: vec![String::from("I")],
+                granularity: 4,
+                ..Metadata::default()
+            })
+            .unwrap();
+        encoder
+            .encode(0, &[vec![1.5, 2.0], vec![0.0, -1.0]])
+            .unwrap();
+        encoder.encode(2, &[vec![3.0], vec![-3.25]]).unwrap();
+        let output = encoder.finish().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(output).unwrap(),
+            "time,I,step_1\n0,1.5,0\n0.25,2,-1\n0.5,3,-3.25\n"
+        );
+    }
+
+    #[test]
+    fn test_given_window_when_parsed_to_csv_then_time_counts_from_its_start() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&fixture::recording(4, &[(5, 1000)], &[(0..8).collect()]))
+            .unwrap();
+        let dir = tempdir().unwrap();
+        let mut command = fixture::command(dir.path(), file.path());
+        command.payload.options.format = crate::encoder::Format::Csv;
+        command.payload.options.start = Some(WindowBound::Sample(6));
+        let output = fixture::parse(&command).unwrap().payload.output;
+
+        let csv = std::fs::read_to_string(&output).unwrap();
+        let times: Vec<&str> = csv
+            .lines()
+            .skip(1)
+            .map(|row| row.split(DELIMITER).next().unwrap())
+            .collect();
+        assert_eq!(times, ["0", "0.25"]);
+        let metadata: Metadata =
+            serde_json::from_slice(&std::fs::read(output.with_file_name("metadata.json")).unwrap())
+                .unwrap();
+        assert_eq!(metadata.first_sample, 6);
+        assert_eq!(metadata.time_of_recipe.to_string(), "05:09:01.500");
+    }
+}
@@ -0,0 +1,368 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Timelike;
+
+use crate::metadata::{Metadata, Sex};
+use crate::recipe::RecipeError;
+use crate::stage::filter::Filter;
+
+use super::{
+    commit, output_error, partial_path, quantize, step_labels, step_scales, OutputEncoder,
+};
+
+pub const FILENAME: &str = "recipe.edf";
+const RECORD_DURATION: u16 = 1;
+const ANNOTATION_SAMPLES: usize = 16;
+const ANNOTATION_LABEL: &str = "EDF Annotations";
+const DATA_RECORDS_OFFSET: u64 = 236;
+const UNKNOWN: &str = "X";
+
+/// Writes an EDF+ (continuous) file with one signal per step, digitised back
+/// to the recorder's own resolution, plus the time-keeping annotation signal.
+///
+/// Records last [`RECORD_DURATION`] seconds; the last record is padded with
+/// zeros. The number of data records is written once every block is in. The
+/// header holds the start to the second, so each record's time-keeping TAL
+/// carries the fraction of a second the first sample falls after it.
+pub struct EdfEncoder {
+    path: PathBuf,
+    /// Nanoseconds past the start time in the header.
+    subsecond: u32,
+    samples_per_record: usize,
+    scales: Vec<f64>,
+    pending: Vec<Vec<i16>>,
+    records: u64,
+    writer: Option<BufWriter<File>>,
+}
+
+/// Left-aligned, space-padded ASCII field of exactly `len` bytes.
+fn field(value: &str, len: usize) -> String {
+    let ascii: String = value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(len)
+        .collect();
+    format!("{ascii:<len$}")
+}
+
+/// Shortest decimal rendering of `value` that fits an 8-byte numeric field.
+fn number(value: f64) -> String {
+    (0..=6)
+        .rev()
+        .map(|precision| {
+            let s = format!("{value:.precision$}");
+            if s.contains('.') {
+                s.trim_end_matches('0').trim_end_matches('.').to_string()
+            } else {
+                s
+            }
+        })
+        .find(|s| s.len() <= 8)
+        .unwrap_or_else(|| format!("{value:.0}"))
+}
+
+/// Onset of a time-keeping TAL, as in `+2` or `+2.75`.
+fn onset(seconds: u64, nanos: u32) -> String {
+    if nanos == 0 {
+        return format!("+{seconds}");
+    }
+    let fraction = format!("{nanos:09}");
+    format!("+{seconds}.{}", fraction.trim_end_matches('0'))
+}
+
+/// EDF+ subfields are separated by spaces, so spaces within one become `_`.
+fn subfield(value: &str) -> String {
+    let value = value.trim();
+    if value.is_empty() {
+        String::from(UNKNOWN)
+    } else {
+        value.replace(' ', "_")
+    }
+}
+
+/// The prefiltering field, in the EDF convention of `HP:0.5Hz N:50Hz`.
+fn prefiltering(filters: &[Filter]) -> String {
+    filters
+        .iter()
+        .map(|filter| match filter {
+            Filter::HighPass { cutoff } => format!("HP:{cutoff}Hz"),
+            Filter::Notch { frequency, .. } => format!("N:{frequency}Hz"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl EdfEncoder {
+    #[must_use]
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(FILENAME),
+            subsecond: 0,
+            samples_per_record: 1,
+            scales: Vec::new(),
+            pending: Vec::new(),
+            records: 0,
+            writer: None,
+        }
+    }
+
+    /// The fixed header record followed by one header record per signal.
+    #[must_use]
+    pub fn header(metadata: &Metadata, records: i64) -> Vec<u8> {
+        let labels = step_labels(metadata);
+        let scales = step_scales(metadata);
+        let signals = labels.len() + 1;
+        let samples_per_record = usize::from(metadata.granularity.max(1) * RECORD_DURATION);
+        let patient = &metadata.patient;
+        let sex = match patient.sex {
+            Sex::Male => "M",
+            Sex::Female => "F",
+            Sex::Unknown => UNKNOWN,
+        };
+        let birth = patient.date_of_birth.map_or_else(
+            || String::from(UNKNOWN),
+            |d| d.format("%d-%b-%Y").to_string().to_uppercase(),
+        );
+        let name = subfield(&format!("{} {}", patient.last_name, patient.first_name));
+        let start = metadata.date_of_recipe.and_time(metadata.time_of_recipe);
+
+        let mut header = String::new();
+        header.push_str(&field("0", 8));
+        header.push_str(&field(
+            &format!("{} {sex} {birth} {name}", subfield(&patient.id)),
+            80,
+        ));
+        header.push_str(&field(
+            &format!(
+                "Startdate {} {UNKNOWN} {UNKNOWN} {}",
+                start.format("%d-%b-%Y").to_string().to_uppercase(),
+                subfield(&metadata.recorder)
+            ),
+            80,
+        ));
+        header.push_str(&field(&start.format("%d.%m.%y").to_string(), 8));
+        header.push_str(&field(&start.format("%H.%M.%S").to_string(), 8));
+        header.push_str(&field(&((signals + 1) * 256).to_string(), 8));
+        header.push_str(&field("EDF+C", 44));
+        header.push_str(&field(&records.to_string(), 8));
+        header.push_str(&field(&RECORD_DURATION.to_string(), 8));
+        header.push_str(&field(&signals.to_string(), 4));
+
+        let mut push_signals = |len: usize, value: &dyn Fn(Option<usize>) -> String| {
+            for i in 0..labels.len() {
+                header.push_str(&field(&value(Some(i)), len));
+            }
+            header.push_str(&field(&value(None), len));
+        };
+        push_signals(16, &|i| {
+            i.map_or_else(
+                || String::from(ANNOTATION_LABEL),
+                |i| format!("ECG {}", labels[i]),
+            )
+        });
+        push_signals(80, &|_| String::new());
+        push_signals(8, &|i| i.map_or_else(String::new, |_| String::from("mV")));
+        push_signals(8, &|i| {
+            i.map_or_else(
+                || String::from("-1"),
+                |i| number(f64::from(i16::MIN) * scales[i]),
+            )
+        });
+        push_signals(8, &|i| {
+            i.map_or_else(
+                || String::from("1"),
+                |i| number(f64::from(i16::MAX) * scales[i]),
+            )
+        });
+        push_signals(8, &|_| i16::MIN.to_string());
+        push_signals(8, &|_| i16::MAX.to_string());
+        let filters = prefiltering(&metadata.filters);
+        push_signals(80, &|i| i.map_or_else(String::new, |_| filters.clone()));
+        push_signals(8, &|i| {
+            i.map_or(ANNOTATION_SAMPLES, |_| samples_per_record)
+                .to_string()
+        });
+        push_signals(32, &|_| String::new());
+        header.into_bytes()
+    }
+
+    fn write_record(&mut self) -> Result<(), RecipeError> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        let mut record = Vec::new();
+        for samples in &mut self.pending {
+            samples.resize(samples.len().max(self.samples_per_record), 0);
+            for sample in samples.drain(..self.samples_per_record) {
+                record.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        let seconds = self.records * u64::from(RECORD_DURATION);
+        let mut annotation = format!("{}\x14\x14\0", onset(seconds, self.subsecond)).into_bytes();
+        annotation.resize(ANNOTATION_SAMPLES * 2, 0);
+        record.extend_from_slice(&annotation);
+        writer
+            .write_all(&record)
+            .map_err(output_error(&self.path))?;
+        self.records += 1;
+        Ok(())
+    }
+}
+
+impl OutputEncoder for EdfEncoder {
+    fn begin(&mut self, metadata: &Metadata) -> Result<(), RecipeError> {
+        // Past 999_999_999 during a leap second, which EDF has no room for.
+        self.subsecond = metadata.time_of_recipe.nanosecond().min(999_999_999);
+        self.samples_per_record = usize::from(metadata.granularity.max(1) * RECORD_DURATION);
+        self.scales = step_scales(metadata);
+        self.pending = vec![Vec::new(); self.scales.len()];
+        let file = File::create(partial_path(&self.path)).map_err(output_error(&self.path))?;
+        let mut writer = BufWriter::new(file);
+        writer
+            .write_all(&Self::header(metadata, -1))
+            .map_err(output_error(&self.path))?;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    fn encode(&mut self, _onset: u64, guacamole: &[Vec<f64>]) -> Result<(), RecipeError> {
+        for ((pending, guac), scale) in self.pending.iter_mut().zip(guacamole).zip(&self.scales) {
+            pending.extend(guac.iter().map(|v| quantize(*v, *scale)));
+        }
+        while self
+            .pending
+            .first()
+            .is_some_and(|p| p.len() >= self.samples_per_record)
+        {
+            self.write_record()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<PathBuf, RecipeError> {
+        if self.pending.first().is_some_and(|p| !p.is_empty()) {
+            self.write_record()?;
+        }
+        if let Some(mut writer) = self.writer.take() {
+            let records = field(&self.records.to_string(), 8);
+            writer
+                .seek(SeekFrom::Start(DATA_RECORDS_OFFSET))
+                .and_then(|_| writer.write_all(records.as_bytes()))
+                .and_then(|()| writer.flush())
+                .and_then(|()| writer.get_ref().sync_all())
+                .map_err(output_error(&self.path))?;
+        }
+        commit(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::{tempdir, NamedTempFile};
+
+    use super::*;
+    use crate::recipe::carne_asade::fixture;
+
+    fn text(bytes: &[u8], at: usize, len: usize) -> String {
+        String::from_utf8_lossy(&bytes[at..at + len])
+            .trim()
+            .to_string()
+    }
+
+    #[test]
+    fn test_given_recording_when_parsed_to_edf_then_header_and_records_match() {
+        let samples = vec![(0..10).collect::<Vec<i16>>(), (0..10).map(|v| -v).collect()];
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&fixture::recording(4, &[(5, 1000), (6, 2000)], &samples))
+            .unwrap();
+        let dir = tempdir().unwrap();
+        let mut command = fixture::command(dir.path(), file.path());
+        command.payload.options.format = crate::encoder::Format::Edf;
+        let output = fixture::parse(&command).unwrap().payload.output;
+        let edf = std::fs::read(output).unwrap();
+
+        assert_eq!(text(&edf, 0, 8), "0");
+        assert_eq!(text(&edf, 88, 80), "Startdate 05-NOV-2008 X X X");
+        assert_eq!(text(&edf, 168, 8), "05.11.08");
+        assert_eq!(text(&edf, 176, 8), "05.09.00");
+        assert_eq!(text(&edf, 184, 8), "1024");
+        assert_eq!(text(&edf, 192, 44), "EDF+C");
+        assert_eq!(text(&edf, 236, 8), "3");
+        assert_eq!(text(&edf, 244, 8), "1");
+        assert_eq!(text(&edf, 252, 4), "3");
+        assert_eq!(text(&edf, 256, 16), "ECG I");
+        assert_eq!(text(&edf, 272, 16), "ECG II");
+        assert_eq!(text(&edf, 288, 16), ANNOTATION_LABEL);
+        assert_eq!(text(&edf, 256 + 3 * 96, 8), "mV");
+        assert_eq!(text(&edf, 256 + 3 * 104 + 8, 8), "-65.536");
+        assert_eq!(text(&edf, 256 + 3 * 112 + 8, 8), "65.534");
+        assert_eq!(text(&edf, 256 + 3 * 216, 8), "4");
+        assert_eq!(text(&edf, 256 + 3 * 216 + 16, 8), "16");
+
+        let record_len = (4 + 4 + ANNOTATION_SAMPLES) * 2;
+        assert_eq!(edf.len(), 1024 + 3 * record_len);
+        let record: Vec<i16> = edf[1024..1024 + record_len]
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(&record[..8], &[0, 1, 2, 3, 0, -1, -2, -3]);
+        assert_eq!(&edf[1024 + 16..1024 + 21], b"+0\x14\x14\0");
+        let last = &edf[1024 + 2 * record_len..];
+        assert_eq!(&last[..8], &[8, 0, 9, 0, 0, 0, 0, 0]);
+        assert_eq!(&last[16..21], b"+2\x14\x14\0");
+    }
+
+    #[test]
+    fn test_given_window_starting_mid_second_when_parsed_to_edf_then_tals_carry_fraction() {
+        let samples = vec![(0..10).collect::<Vec<i16>>()];
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&fixture::recording(4, &[(5, 1000)], &samples))
+            .unwrap();
+        let dir = tempdir().unwrap();
+        let mut command = fixture::command(dir.path(), file.path());
+        command.payload.options.format = crate::encoder::Format::Edf;
+        command.payload.options.start = Some(crate::recipe::WindowBound::Sample(3));
+        let output = fixture::parse(&command).unwrap().payload.output;
+        let edf = std::fs::read(output).unwrap();
+
+        assert_eq!(text(&edf, 176, 8), "05.09.00");
+        let record_len = (4 + ANNOTATION_SAMPLES) * 2;
+        assert_eq!(edf.len(), 768 + 2 * record_len);
+        assert_eq!(&edf[768 + 8..768 + 16], b"+0.75\x14\x14\0");
+        assert_eq!(
+            &edf[768 + record_len + 8..768 + record_len + 16],
+            b"+1.75\x14\x14\0"
+        );
+    }
+
+    #[test]
+    fn test_given_nanoseconds_when_onset_then_trailing_zeros_dropped() {
+        assert_eq!(onset(0, 0), "+0");
+        assert_eq!(onset(3, 500_000_000), "+3.5");
+        assert_eq!(onset(3, 1), "+3.000000001");
+    }
+
+    #[test]
+    fn test_given_filters_when_prefiltering_then_edf_convention() {
+        let filters = [
+            Filter::HighPass { cutoff: 0.5 },
+            Filter::Notch {
+                frequency: 50.0,
+                q: 30.0,
+                harmonics: 2,
+            },
+        ];
+        assert_eq!(prefiltering(&filters), "HP:0.5Hz N:50Hz");
+        assert_eq!(prefiltering(&[]), "");
+    }
+}
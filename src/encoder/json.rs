@@ -0,0 +1,252 @@
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::metadata::Metadata;
+use crate::recipe::RecipeError;
+
+use super::{commit, output_error, partial_path, OutputEncoder};
+
+const DELIMITER: &str = ",";
+pub const FILENAME: &str = "recipe.json";
+const GUACAMOLE_START_SLICE: &str = ",\"guacamole\":[[";
+const GUACAMOLE_END_SLICE: &str = "]]}";
+
+/// A [`RecipeError::OutputWrite`] for `path` holding something other than
+/// what was written to it.
+fn malformed(path: &Path, reason: &str) -> RecipeError {
+    RecipeError::OutputWrite {
+        path: path.to_path_buf(),
+        source: io::Error::new(io::ErrorKind::InvalidData, reason),
+    }
+}
+
+/// Writes the metadata followed by a `guacamole` array holding one array of
+/// samples per step.
+///
+/// Steps are stored one after the other, so each block is first written to a
+/// chunk file per step and the chunks are merged once every block is in.
+pub struct JsonEncoder {
+    dir: PathBuf,
+    keep_intermediates: bool,
+    metadata: Vec<u8>,
+    files: Vec<(usize, u64, String)>,
+}
+
+impl JsonEncoder {
+    #[must_use]
+    pub fn new(dir: &Path, keep_intermediates: bool) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            keep_intermediates,
+            metadata: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::OutputWrite`] when the chunk cannot be written.
+    pub fn write_chunk(
+        dir: &Path,
+        onset: u64,
+        offset: u64,
+        step: usize,
+        guac: &Vec<f64>,
+    ) -> Result<(usize, u64, String), RecipeError> {
+        let filename = format!("{onset}_{offset}_{step}.json");
+        let path = dir.join(&filename);
+        let file = File::create(&path).map_err(output_error(&path))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, &guac)
+            .map_err(io::Error::from)
+            .and_then(|()| writer.flush())
+            .map_err(output_error(&path))?;
+        Ok((step, onset, filename))
+    }
+
+    /// Merges the chunk files into the recipe, written under a temporary name
+    /// and renamed into place so a failure never leaves a partial recipe.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::OutputWrite`] when the metadata is not a JSON
+    /// object, a chunk cannot be read back or is truncated, or the merged
+    /// recipe cannot be written.
+    pub fn merge(
+        dir: &Path,
+        metadata: &[u8],
+        files: &[(usize, u64, String)],
+    ) -> Result<PathBuf, RecipeError> {
+        let recipe_path = dir.join(FILENAME);
+        let partial = partial_path(&recipe_path);
+        if let Err(err) = Self::write_merged(dir, metadata, files, &partial) {
+            let _ = fs::remove_file(&partial);
+            return Err(err);
+        }
+        commit(&recipe_path)
+    }
+
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::OutputWrite`] when a chunk file cannot be removed.
+    pub fn remove_chunks(dir: &Path, files: &[(usize, u64, String)]) -> Result<(), RecipeError> {
+        for f in files {
+            let chunk_path = dir.join(&f.2);
+            fs::remove_file(&chunk_path).map_err(output_error(&chunk_path))?;
+        }
+        Ok(())
+    }
+
+    fn write_merged(
+        dir: &Path,
+        metadata: &[u8],
+        files: &[(usize, u64, String)],
+        recipe_path: &Path,
+    ) -> Result<(), RecipeError> {
+        let mut header = metadata
+            .strip_suffix(b"}")
+            .ok_or_else(|| malformed(recipe_path, "metadata is not a JSON object"))?
+            .to_vec();
+        header.extend_from_slice(GUACAMOLE_START_SLICE.as_bytes());
+        fs::write(recipe_path, &header).map_err(output_error(recipe_path))?;
+        let mut recipe_file = fs::OpenOptions::new()
+            .append(true)
+            .open(recipe_path)
+            .map_err(output_error(recipe_path))?;
+        let mut current_step: usize = usize::MAX;
+        for f in files {
+            if current_step == usize::MAX {
+                current_step = f.0;
+            }
+
+            if f.0 != current_step {
+                recipe_file
+                    .write_all(String::from("],[").as_bytes())
+                    .map_err(output_error(recipe_path))?;
+                current_step = f.0;
+            }
+
+            if f.1 > 0 {
+                recipe_file
+                    .write_all(DELIMITER.as_bytes())
+                    .map_err(output_error(recipe_path))?;
+            }
+
+            let chunk_path = dir.join(&f.2);
+            let r = fs::read(&chunk_path).map_err(output_error(&chunk_path))?;
+            let samples = r
+                .strip_prefix(b"[")
+                .and_then(|r| r.strip_suffix(b"]"))
+                .ok_or_else(|| malformed(&chunk_path, "chunk is not a JSON array"))?;
+            recipe_file
+                .write_all(samples)
+                .map_err(output_error(recipe_path))?;
+        }
+        recipe_file
+            .write_all(String::from(GUACAMOLE_END_SLICE).as_bytes())
+            .and_then(|()| recipe_file.sync_all())
+            .map_err(output_error(recipe_path))
+    }
+}
+
+impl OutputEncoder for JsonEncoder {
+    fn begin(&mut self, metadata: &Metadata) -> Result<(), RecipeError> {
+        let recipe_path = self.dir.join(FILENAME);
+        self.metadata = serde_json::to_vec(metadata)
+            .map_err(io::Error::from)
+            .map_err(output_error(&recipe_path))?;
+        Ok(())
+    }
+
+    fn encode(&mut self, onset: u64, guacamole: &[Vec<f64>]) -> Result<(), RecipeError> {
+        for (step, guac) in guacamole.iter().enumerate() {
+            let offset = onset + guac.len() as u64;
+            self.files
+                .push(Self::write_chunk(&self.dir, onset, offset, step, guac)?);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<PathBuf, RecipeError> {
+        self.files.sort_by(|a, b| match a.0.cmp(&b.0) {
+            Ordering::Equal => a.1.cmp(&b.1),
+            other => other,
+        });
+        let output = Self::merge(&self.dir, &self.metadata, &self.files)?;
+        if !self.keep_intermediates {
+            Self::remove_chunks(&self.dir, &self.files)?;
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            number_of_steps: 2,
+            steps: vec![String::from("I"), String::from("II")],
+            granularity: 200,
+            ..Metadata::default()
+        }
+    }
+
+    #[test]
+    fn test_given_blocks_when_encode_then_steps_merged_in_order() {
+        let dir = tempdir().unwrap();
+        let mut encoder = JsonEncoder::new(dir.path(), false);
+        encoder.begin(&metadata()).unwrap();
+        encoder
+            .encode(0, &[vec![1.0, 2.0], vec![-1.0, -2.0]])
+            .unwrap();
+        encoder.encode(2, &[vec![3.0], vec![-3.0]]).unwrap();
+        let output = encoder.finish().unwrap();
+        let recipe: serde_json::Value = serde_json::from_slice(&fs::read(output).unwrap()).unwrap();
+        assert_eq!(
+            recipe["guacamole"],
+            serde_json::json!([[1.0, 2.0, 3.0], [-1.0, -2.0, -3.0]])
+        );
+        assert_eq!(recipe["steps"], serde_json::json!(["I", "II"]));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_given_missing_chunk_when_merge_then_no_recipe_left_behind() {
+        let dir = tempdir().unwrap();
+        let metadata = serde_json::to_vec(&metadata()).unwrap();
+        assert!(matches!(
+            JsonEncoder::merge(dir.path(), &metadata, &[(0, 0, String::from("0_3_0.json"))]),
+            Err(RecipeError::OutputWrite { .. })
+        ));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_given_empty_metadata_or_truncated_chunk_when_merge_then_output_write() {
+        let dir = tempdir().unwrap();
+        assert!(matches!(
+            JsonEncoder::merge(dir.path(), b"", &[]),
+            Err(RecipeError::OutputWrite { .. })
+        ));
+
+        let metadata = serde_json::to_vec(&metadata()).unwrap();
+        for chunk in [&b""[..], b"[", b"[1.0,2."] {
+            fs::write(dir.path().join("0_3_0.json"), chunk).unwrap();
+            let result =
+                JsonEncoder::merge(dir.path(), &metadata, &[(0, 0, String::from("0_3_0.json"))]);
+            match result {
+                Err(RecipeError::OutputWrite { path, .. }) => {
+                    assert_eq!(path, dir.path().join("0_3_0.json"));
+                }
+                other => panic!("unexpected result: {other:?}"),
+            }
+        }
+        assert!(!dir.path().join(FILENAME).exists());
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::metadata::Metadata;
+use crate::recipe::RecipeError;
+
+use super::{commit, output_error, partial_path, OutputEncoder};
+
+pub const FILENAME: &str = "recipe.npy";
+const MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+const HEADER_LEN: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dtype {
+    F32,
+    F64,
+}
+
+impl Dtype {
+    fn descr(self) -> &'static str {
+        match self {
+            Self::F32 => "<f4",
+            Self::F64 => "<f8",
+        }
+    }
+}
+
+/// Writes a NumPy `.npy` file holding a C-ordered `(samples, steps)` array.
+///
+/// The header is reserved up front and rewritten with the final shape once
+/// every block has been written.
+pub struct NpyEncoder {
+    path: PathBuf,
+    dtype: Dtype,
+    steps: usize,
+    rows: u64,
+    writer: Option<BufWriter<File>>,
+}
+
+impl NpyEncoder {
+    #[must_use]
+    pub fn new(dir: &Path, dtype: Dtype) -> Self {
+        Self {
+            path: dir.join(FILENAME),
+            dtype,
+            steps: 0,
+            rows: 0,
+            writer: None,
+        }
+    }
+
+    /// Magic, version and a space-padded header dictionary, `HEADER_LEN` bytes
+    /// in total.
+    #[must_use]
+    pub fn header(dtype: Dtype, rows: u64, steps: usize) -> Vec<u8> {
+        let dict = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': ({rows}, {steps}), }}",
+            dtype.descr()
+        );
+        let mut header = MAGIC.to_vec();
+        let len = HEADER_LEN - MAGIC.len() - 2;
+        header.extend_from_slice(&u16::try_from(len).unwrap_or(u16::MAX).to_le_bytes());
+        header.extend_from_slice(format!("{dict:<width$}", width = len - 1).as_bytes());
+        header.push(b'\n');
+        header
+    }
+}
+
+impl OutputEncoder for NpyEncoder {
+    fn begin(&mut self, metadata: &Metadata) -> Result<(), RecipeError> {
+        self.steps = usize::from(metadata.number_of_steps);
+        let partial = partial_path(&self.path);
+        let file = File::create(&partial).map_err(output_error(&self.path))?;
+        let mut writer = BufWriter::new(file);
+        writer
+            .write_all(&Self::header(self.dtype, 0, self.steps))
+            .map_err(output_error(&self.path))?;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    fn encode(&mut self, _onset: u64, guacamole: &[Vec<f64>]) -> Result<(), RecipeError> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        let samples = guacamole.first().map_or(0, Vec::len);
+        for i in 0..samples {
+            for guac in guacamole {
+                #[allow(clippy::cast_possible_truncation)]
+                let bytes = match self.dtype {
+                    Dtype::F32 => writer.write_all(&(guac[i] as f32).to_le_bytes()),
+                    Dtype::F64 => writer.write_all(&guac[i].to_le_bytes()),
+                };
+                bytes.map_err(output_error(&self.path))?;
+            }
+        }
+        self.rows += samples as u64;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<PathBuf, RecipeError> {
+        if let Some(mut writer) = self.writer.take() {
+            let header = Self::header(self.dtype, self.rows, self.steps);
+            writer
+                .seek(SeekFrom::Start(0))
+                .and_then(|_| writer.write_all(&header))
+                .and_then(|()| writer.flush())
+                .and_then(|()| writer.get_ref().sync_all())
+                .map_err(output_error(&self.path))?;
+        }
+        commit(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_given_shape_when_header_then_padded_to_header_len() {
+        let header = NpyEncoder::header(Dtype::F32, 300_000, 12);
+        assert_eq!(header.len(), HEADER_LEN);
+        assert_eq!(&header[..8], MAGIC);
+        assert_eq!(header[HEADER_LEN - 1], b'\n');
+        assert!(String::from_utf8_lossy(&header)
+            .contains("{'descr': '<f4', 'fortran_order': False, 'shape': (300000, 12), }"));
+    }
+
+    #[test]
+    fn test_given_blocks_when_encode_then_rows_of_steps() {
+        let dir = tempdir().unwrap();
+        let mut encoder = NpyEncoder::new(dir.path(), Dtype::F64);
+        encoder
+            .begin(&Metadata {
+                number_of_steps: 2,
+                ..Metadata::default()
+            })
+            .unwrap();
+        encoder
+            .encode(0, &[vec![1.0, 2.0], vec![-1.0, -2.0]])
+            .unwrap();
+        encoder.encode(2, &[vec![3.0], vec![-3.0]]).unwrap();
+        let bytes = std::fs::read(encoder.finish().unwrap()).unwrap();
+        assert_eq!(&bytes[..HEADER_LEN], NpyEncoder::header(Dtype::F64, 3, 2));
+        let values: Vec<f64> = bytes[HEADER_LEN..]
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, vec![1.0, -1.0, 2.0, -2.0, 3.0, -3.0]);
+    }
+}
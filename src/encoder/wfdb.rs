@@ -0,0 +1,204 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::metadata::Metadata;
+use crate::recipe::RecipeError;
+
+use super::{
+    commit, output_error, partial_path, quantize, step_labels, step_scales, OutputEncoder,
+};
+
+pub const RECORD_NAME: &str = "recipe";
+const FORMAT: u16 = 16;
+const ADC_RESOLUTION: u16 = 16;
+
+/// Running initial value and checksum of one signal, as the header needs them.
+#[derive(Debug, Clone, Copy, Default)]
+struct Signal {
+    initial_value: Option<i16>,
+    checksum: i16,
+}
+
+impl Signal {
+    fn push(&mut self, sample: i16) {
+        self.initial_value.get_or_insert(sample);
+        self.checksum = self.checksum.wrapping_add(sample);
+    }
+}
+
+/// Writes a PhysioNet WFDB record: a `.hea` header and a format 16 `.dat`
+/// signal file holding interleaved little-endian 16-bit samples.
+///
+/// CARNE1.0 samples are already stored that way, so raw blocks are copied
+/// through untouched and only decoded values are re-quantized.
+pub struct WfdbEncoder {
+    header_path: PathBuf,
+    signal_path: PathBuf,
+    metadata: Option<Metadata>,
+    scales: Vec<f64>,
+    signals: Vec<Signal>,
+    frames: u64,
+    writer: Option<BufWriter<File>>,
+}
+
+impl WfdbEncoder {
+    #[must_use]
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            header_path: dir.join(format!("{RECORD_NAME}.hea")),
+            signal_path: dir.join(format!("{RECORD_NAME}.dat")),
+            metadata: None,
+            scales: Vec::new(),
+            signals: Vec::new(),
+            frames: 0,
+            writer: None,
+        }
+    }
+
+    /// The record line followed by one line per signal.
+    fn header(&self, metadata: &Metadata) -> String {
+        let start = metadata.date_of_recipe.and_time(metadata.time_of_recipe);
+        let mut header = format!(
+            "{RECORD_NAME} {} {} {} {} {}\n",
+            self.signals.len(),
+            metadata.granularity,
+            self.frames,
+            start.format("%H:%M:%S"),
+            start.format("%d/%m/%Y"),
+        );
+        for ((label, scale), signal) in step_labels(metadata)
+            .iter()
+            .zip(&self.scales)
+            .zip(&self.signals)
+        {
+            header.push_str(&format!(
+                "{RECORD_NAME}.dat {FORMAT} {}(0)/mV {ADC_RESOLUTION} 0 {} {} 0 {label}\n",
+                1.0 / scale,
+                signal.initial_value.unwrap_or(0),
+                signal.checksum,
+            ));
+        }
+        header
+    }
+
+    fn write_frames(&mut self, samples: &[i16]) -> Result<(), RecipeError> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        if self.signals.is_empty() {
+            return Ok(());
+        }
+        let count = self.signals.len();
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for (i, sample) in samples.iter().enumerate() {
+            self.signals[i % count].push(*sample);
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        writer
+            .write_all(&bytes)
+            .map_err(output_error(&self.signal_path))?;
+        self.frames += (samples.len() / count) as u64;
+        Ok(())
+    }
+}
+
+impl OutputEncoder for WfdbEncoder {
+    fn begin(&mut self, metadata: &Metadata) -> Result<(), RecipeError> {
+        self.scales = step_scales(metadata);
+        self.signals = vec![Signal::default(); self.scales.len()];
+        self.metadata = Some(metadata.clone());
+        let file = File::create(partial_path(&self.signal_path))
+            .map_err(output_error(&self.signal_path))?;
+        self.writer = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    fn encode(&mut self, _onset: u64, guacamole: &[Vec<f64>]) -> Result<(), RecipeError> {
+        let samples = guacamole.first().map_or(0, Vec::len);
+        let mut frames = Vec::with_capacity(samples * guacamole.len());
+        for i in 0..samples {
+            for (guac, scale) in guacamole.iter().zip(&self.scales) {
+                frames.push(quantize(guac[i], *scale));
+            }
+        }
+        self.write_frames(&frames)
+    }
+
+    fn accepts_raw(&self) -> bool {
+        true
+    }
+
+    fn encode_raw(&mut self, _onset: u64, raw: &[u8]) -> Result<(), RecipeError> {
+        let samples: Vec<i16> = raw
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        self.write_frames(&samples)
+    }
+
+    fn finish(&mut self) -> Result<PathBuf, RecipeError> {
+        if let Some(mut writer) = self.writer.take() {
+            writer
+                .flush()
+                .and_then(|()| writer.get_ref().sync_all())
+                .map_err(output_error(&self.signal_path))?;
+        }
+        commit(&self.signal_path)?;
+        if let Some(metadata) = self.metadata.take() {
+            fs::write(partial_path(&self.header_path), self.header(&metadata))
+                .map_err(output_error(&self.header_path))?;
+        }
+        commit(&self.header_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::{tempdir, NamedTempFile};
+
+    use super::*;
+    use crate::recipe::carne_asade::fixture;
+
+    #[test]
+    fn test_given_recording_when_parsed_to_wfdb_then_samples_copied_as_is() {
+        let samples = vec![vec![-3, 7, 32767], vec![1, -32768, 0]];
+        let recording = fixture::recording(250, &[(5, 5000), (6, 2500)], &samples);
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&recording).unwrap();
+        let dir = tempdir().unwrap();
+        let mut command = fixture::command(dir.path(), file.path());
+        command.payload.options.format = crate::encoder::Format::Wfdb;
+        let header_path = fixture::parse(&command).unwrap().payload.output;
+        let signal_path = header_path.with_extension("dat");
+        assert_eq!(fs::read(signal_path).unwrap(), &recording[522..]);
+        assert_eq!(
+            fs::read_to_string(header_path).unwrap(),
+            "recipe 2 250 3 05:09:00 05/11/2008\n\
+             recipe.dat 16 200(0)/mV 16 0 -3 -32765 0 I\n\
+             recipe.dat 16 400(0)/mV 16 0 1 -32767 0 II\n"
+        );
+    }
+
+    #[test]
+    fn test_given_decoded_blocks_when_encode_then_requantized_to_recorder_units() {
+        let dir = tempdir().unwrap();
+        let mut encoder = WfdbEncoder::new(dir.path());
+        encoder
+            .begin(&Metadata {
+                number_of_steps: 1,
+                unit_conversion: vec![5000],
+                granularity: 250,
+                ..Metadata::default()
+            })
+            .unwrap();
+        encoder.encode(0, &[vec![-0.015, 0.035]]).unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(
+            fs::read(dir.path().join("recipe.dat")).unwrap(),
+            [(-3_i16).to_le_bytes(), 7_i16.to_le_bytes()].concat()
+        );
+    }
+}
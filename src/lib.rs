@@ -1,6 +1,9 @@
+pub mod cli;
 pub mod command;
 pub mod config;
+pub mod encoder;
 pub mod event;
 pub mod metadata;
 pub mod notifier;
 pub mod recipe;
+pub mod stage;
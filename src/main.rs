@@ -1,26 +1,118 @@
 use std::rc::Rc;
+use std::sync::mpsc;
 
-use taqueria::{command, command::CommandHandler, config, event::EventHandler, notifier, recipe};
+use clap::Parser;
+use taqueria::{
+    cli, command, command::CommandHandler, config, event::EventHandler, notifier,
+    notifier::Notifier, recipe,
+};
 
-fn main() {
-    env_logger::init();
-    let conf =
-        config::Config::read(config::Config::path()).expect("Could not initialize configuration.");
-    let notifier = Rc::new(notifier::console::ConsoleNotifier {});
+fn recipes() -> recipe::ParseRecipeCommandHandler {
     let carne_asade = Box::new(recipe::carne_asade::CarneAsada {});
     let mut parse_recipe_command_handler = recipe::ParseRecipeCommandHandler::default();
-    let recipe_parsed_event_handler = recipe::RecipeParsedEventHandler { notifier };
     parse_recipe_command_handler.register(carne_asade);
-    let cmd = command::Command::<recipe::ParseRecipe> {
+    parse_recipe_command_handler
+}
+
+/// Parses the recordings `conf` names and returns the exit code.
+fn parse(conf: config::Config, notifier: &Rc<dyn Notifier>) -> i32 {
+    let (beats_detected, beats_detected_events) = mpsc::channel();
+    let mut recipes = recipes();
+    let (rhythm_reported, rhythm_reported_events) = mpsc::channel();
+    recipes.on_beats_detected(beats_detected);
+    recipes.on_rhythm_reported(rhythm_reported);
+    let parse_batch_command_handler = recipe::batch::ParseBatchCommandHandler { recipes };
+    let batch_parsed_event_handler = recipe::batch::BatchParsedEventHandler {
+        notifier: notifier.clone(),
+    };
+    let beats_detected_event_handler = recipe::BeatsDetectedEventHandler {
+        notifier: notifier.clone(),
+    };
+    let rhythm_reported_event_handler = recipe::RhythmReportedEventHandler {
+        notifier: notifier.clone(),
+    };
+    let cmd = command::Command::<recipe::batch::ParseBatch> {
         command_type: 0,
-        payload: recipe::ParseRecipe {
+        payload: recipe::batch::ParseBatch {
             basepath: conf.basepath,
-            filepath: conf.filepath,
-            identifier: uuid::Uuid::new_v4(),
+            inputs: conf.filepath,
+            concurrency: conf.concurrency,
+            options: conf.options,
         },
     };
-    let evt = parse_recipe_command_handler
-        .handle(&cmd)
-        .expect("Could not handle recipe.");
-    recipe_parsed_event_handler.handle(evt);
+    match parse_batch_command_handler.handle(&cmd) {
+        Ok(evt) => {
+            let failures = evt.payload.failures();
+            batch_parsed_event_handler.handle(evt);
+            for evt in beats_detected_events.try_iter() {
+                beats_detected_event_handler.handle(evt);
+            }
+            for evt in rhythm_reported_events.try_iter() {
+                rhythm_reported_event_handler.handle(evt);
+            }
+            i32::from(failures > 0)
+        }
+        Err(err) => {
+            notifier.failure(format!("{}: {err}", cmd.payload.inputs));
+            1
+        }
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let cli = cli::Cli::parse();
+    let conf = config::Config::read(config::Config::path());
+    let notifier: Rc<dyn Notifier> = Rc::new(notifier::console::ConsoleNotifier {});
+    let code = match cli.command {
+        None => {
+            let mut conf = conf.expect("Could not initialize configuration.");
+            conf.options.lenient |= cli.lenient;
+            parse(conf, &notifier)
+        }
+        Some(cli::Command::Parse(args)) => {
+            let mut conf = args.apply(conf.unwrap_or_default());
+            conf.options.lenient |= cli.lenient;
+            parse(conf, &notifier)
+        }
+        Some(cli::Command::Inspect { file, json }) => {
+            match recipe::carne_asade::CarneAsada::inspect(&file) {
+                Ok(inspection) if json => {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&inspection)
+                            .expect("Could not serialize inspection.")
+                    );
+                    0
+                }
+                Ok(inspection) => {
+                    println!("{inspection}");
+                    0
+                }
+                Err(err) => {
+                    notifier.failure(format!("{file}: {err}"));
+                    1
+                }
+            }
+        }
+        Some(cli::Command::Validate { file }) => {
+            match recipe::carne_asade::CarneAsada::validate(&file) {
+                Ok(_) => {
+                    println!("{file}: valid");
+                    0
+                }
+                Err(err) => {
+                    notifier.failure(format!("{file}: {err}"));
+                    1
+                }
+            }
+        }
+        Some(cli::Command::ListRecipes) => {
+            for identifier in recipes().identifiers() {
+                println!("{identifier}");
+            }
+            0
+        }
+    };
+    std::process::exit(code);
 }
@@ -1,22 +1,205 @@
-use std::{fs::File, io::BufWriter, path::PathBuf};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Sex {
+    #[default]
+    Unknown,
+    Male,
+    Female,
+}
+
+impl From<u16> for Sex {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => Self::Male,
+            2 => Self::Female,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl From<Sex> for u16 {
+    fn from(value: Sex) -> Self {
+        match value {
+            Sex::Unknown => 0,
+            Sex::Male => 1,
+            Sex::Female => 2,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Race {
+    #[default]
+    Unknown,
+    Caucasian,
+    Black,
+    Oriental,
+}
+
+impl From<u16> for Race {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => Self::Caucasian,
+            2 => Self::Black,
+            3 => Self::Oriental,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl From<Race> for u16 {
+    fn from(value: Race) -> Self {
+        match value {
+            Race::Unknown => 0,
+            Race::Caucasian => 1,
+            Race::Black => 2,
+            Race::Oriental => 3,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Pacemaker {
+    #[default]
+    Unspecified,
+    None,
+    UnknownType,
+    SingleChamberUnipolar,
+    DualChamberUnipolar,
+    SingleChamberBipolar,
+    DualChamberBipolar,
+}
+
+impl From<u16> for Pacemaker {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => Self::None,
+            1 => Self::UnknownType,
+            2 => Self::SingleChamberUnipolar,
+            3 => Self::DualChamberUnipolar,
+            4 => Self::SingleChamberBipolar,
+            5 => Self::DualChamberBipolar,
+            _ => Self::Unspecified,
+        }
+    }
+}
+
+impl From<Pacemaker> for u16 {
+    fn from(value: Pacemaker) -> Self {
+        match value {
+            Pacemaker::Unspecified => 0xFFF7,
+            Pacemaker::None => 0,
+            Pacemaker::UnknownType => 1,
+            Pacemaker::SingleChamberUnipolar => 2,
+            Pacemaker::DualChamberUnipolar => 3,
+            Pacemaker::SingleChamberBipolar => 4,
+            Pacemaker::DualChamberBipolar => 5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Patient {
+    pub first_name: String,
+    pub last_name: String,
+    pub id: String,
+    pub sex: Sex,
+    pub race: Race,
+    pub date_of_birth: Option<chrono::NaiveDate>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Metadata {
+    /// Samples per step in the output, fewer than recorded for a window.
     pub size: u32,
+    /// When the first sample of the output was recorded.
     pub date_of_recipe: chrono::NaiveDate,
     pub time_of_recipe: chrono::NaiveTime,
+    /// Index in the recording, at its recorded sample rate, of the first
+    /// sample of the output.
+    #[serde(default)]
+    pub first_sample: u64,
     pub number_of_steps: u16,
     pub steps: Vec<String>,
     pub units: f32,
     pub granularity: u16,
+    pub variable_block_size: u32,
+    pub variable_block_offset: u32,
+    pub sample_offset: u32,
+    pub file_version: i16,
+    pub patient: Patient,
+    pub date_of_file: Option<chrono::NaiveDate>,
+    pub step_quality: Vec<i16>,
+    /// Nanovolts per least significant bit, for each step.
+    pub unit_conversion: Vec<i32>,
+    /// Steps computed from other steps rather than recorded, such as the
+    /// limb leads derived from I and II.
+    #[serde(default)]
+    pub derived_steps: Vec<String>,
+    /// Filters applied to every step, in the order they ran.
+    #[serde(default)]
+    pub filters: Vec<crate::stage::filter::Filter>,
+    pub pacemaker: Pacemaker,
+    pub recorder: String,
+    pub proprietary: String,
+    pub copyright: String,
+    pub variable_block: Vec<u8>,
+    pub variable_block_text: Option<String>,
+}
+
+/// Writes `value` to `path` as JSON, as the metadata and the other files next
+/// to an output are.
+///
+/// # Errors
+///
+/// Returns an error when the file cannot be created or written.
+pub fn store<T: Serialize>(value: &T, path: &Path) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, value)?;
+    writer.flush()
 }
 
 impl Metadata {
-    pub fn store(&self, path: PathBuf) {
-        let file = File::create(path).expect("Could not create file.");
-        let writer = BufWriter::new(file);
-        serde_json::to_writer(writer, self).expect("Could not write json.");
+    /// # Errors
+    ///
+    /// Returns an error when the file cannot be created or written.
+    pub fn store(&self, path: PathBuf) -> io::Result<()> {
+        store(self, &path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_given_codes_when_from_then_enums() {
+        assert_eq!(Sex::from(2), Sex::Female);
+        assert_eq!(Sex::from(0xFFF7), Sex::Unknown);
+        assert_eq!(Race::from(3), Race::Oriental);
+        assert_eq!(Pacemaker::from(0), Pacemaker::None);
+        assert_eq!(Pacemaker::from(5), Pacemaker::DualChamberBipolar);
+        assert_eq!(Pacemaker::from(0xFFF7), Pacemaker::Unspecified);
+    }
+
+    #[test]
+    fn test_given_enums_when_into_code_then_round_trip() {
+        for code in 0..=5 {
+            assert_eq!(u16::from(Pacemaker::from(code)), code);
+        }
+        for code in 0..=3 {
+            assert_eq!(u16::from(Race::from(code)), code);
+        }
+        for code in 0..=2 {
+            assert_eq!(u16::from(Sex::from(code)), code);
+        }
     }
 }
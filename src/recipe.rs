@@ -1,37 +1,413 @@
+pub mod batch;
 pub mod carne_asade;
 pub mod null;
+pub mod source;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::mpsc::Sender;
+use std::{fmt, fs, io};
 
+use log::warn;
 use serde::{Deserialize, Serialize};
 
 use crate::command::{self, Command, CommandHandler};
+use crate::encoder::output_error;
 use crate::event::{Event, EventHandler};
+use crate::metadata;
+use crate::stage::beats::{self, BeatDetector};
+use crate::stage::filter::{Filter, ZeroPhase};
+use crate::stage::leads::DerivedLeads;
+use crate::stage::resample::Resample;
+use crate::stage::rhythm::{self, Rhythm, Thresholds};
+use crate::stage::{Pipeline, Stage};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RecipeParsed {
-    output: PathBuf,
+    pub output: PathBuf,
 }
 
-#[derive(Debug)]
+/// Raised after [`RecipeParsed`] once the beats of a recording are written.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BeatsDetected {
+    pub output: PathBuf,
+    pub step: String,
+    pub beats: usize,
+}
+
+/// Raised after [`BeatsDetected`] once the heart-rate trend and rate
+/// episodes of those beats are written.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RhythmReported {
+    pub output: PathBuf,
+    pub step: String,
+    pub minutes: usize,
+    pub episodes: usize,
+}
+
+#[derive(Debug, Default)]
 pub struct ParseRecipe {
     pub basepath: String,
+    /// A recording, a `.gz` of one, or a `.zip` holding one; `#member`
+    /// picks the file out of a zip holding several.
     pub filepath: String,
     pub identifier: uuid::Uuid,
+    pub options: ParseOptions,
+}
+
+/// How a recording is parsed, the same for every file of a batch; read from
+/// `config.json` and the command line alike.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct ParseOptions {
+    /// Accept files whose header checksum does not match, or that have none
+    /// (stored as `0x0000`).
+    pub lenient: bool,
+    /// Keep the per-chunk files once they have been merged.
+    pub keep_intermediates: bool,
+    pub format: crate::encoder::Format,
+    /// First sample to parse; the start of the recording when `None`.
+    pub start: Option<WindowBound>,
+    /// Sample to stop before; the end of the recording when `None`.
+    pub end: Option<WindowBound>,
+    /// Steps to parse, in the order given; every step when empty.
+    pub steps: Vec<StepSelector>,
+    /// Sample rate, in Hz, to resample every step to before filtering.
+    pub resample: Option<u16>,
+    /// Filters applied to every step, in order, before leads are derived,
+    /// such as `[{"type": "high-pass", "cutoff": 0.5}, {"type": "notch",
+    /// "frequency": 50}]`.
+    pub filters: Vec<Filter>,
+    /// Append the limb leads III, aVR, aVL and aVF when I and II are parsed.
+    pub derive_leads: bool,
+    /// Step of the output to find R peaks on, written to `beats.json`.
+    pub beats: Option<StepSelector>,
+    /// Rates the beats are reported against in `rhythm.json`, as in
+    /// `{"bradycardia": 50, "tachycardia": 100, "min_seconds": 30}`; no
+    /// report when `None`.
+    pub rhythm: Option<Thresholds>,
+}
+
+/// One end of the part of a recording to parse.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum WindowBound {
+    /// Index of a sample, counted per step from the start of the recording.
+    Sample(u64),
+    /// Wall-clock time on the recorder's clock, the one `date_of_recipe`
+    /// and `time_of_recipe` are read from.
+    Time(chrono::NaiveDateTime),
 }
 
-pub trait Recipe {
-    fn parse(&self, command: &Command<ParseRecipe>) -> Option<Event<RecipeParsed>>;
+impl std::str::FromStr for WindowBound {
+    type Err = String;
+
+    /// A sample index such as `72000`, or a time such as
+    /// `2008-11-05T05:15:00` or `2008-11-05 05:15:00.500`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(sample) = s.parse() {
+            return Ok(Self::Sample(sample));
+        }
+        ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+            .iter()
+            .find_map(|format| chrono::NaiveDateTime::parse_from_str(s, format).ok())
+            .map(Self::Time)
+            .ok_or_else(|| format!("`{s}` is neither a sample index nor a date and time"))
+    }
+}
+
+/// A step of a recording, by its name in the header or its position.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum StepSelector {
+    Index(usize),
+    /// A name such as `II` or `V1`, matched regardless of case.
+    Name(String),
+}
+
+impl std::str::FromStr for StepSelector {
+    type Err = String;
+
+    /// An index such as `0`, or any other text as a name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(String::from("a step needs a name or an index"));
+        }
+        Ok(s.parse()
+            .map_or_else(|_| Self::Name(s.to_string()), Self::Index))
+    }
+}
+
+impl fmt::Display for StepSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "{index}"),
+            Self::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl ParseRecipe {
+    /// Directory the handler creates for a parser's output.
+    #[must_use]
+    pub fn output_dir(&self) -> PathBuf {
+        PathBuf::from(&self.basepath).join(self.identifier.to_string())
+    }
+
+    /// The post-processing stages this recipe asks for, in the order they run.
+    #[must_use]
+    pub fn pipeline(&self) -> Pipeline {
+        let options = &self.options;
+        let mut stages: Vec<Box<dyn Stage>> = Vec::new();
+        if let Some(rate) = options.resample {
+            stages.push(Box::new(Resample::new(rate)));
+        }
+        for filter in &options.filters {
+            stages.push(Box::new(ZeroPhase::new(*filter)));
+        }
+        if options.derive_leads {
+            stages.push(Box::new(DerivedLeads::default()));
+        }
+        Pipeline::new(stages, options.beats.clone().map(BeatDetector::new))
+    }
+}
+
+#[derive(Debug)]
+pub enum RecipeError {
+    Open {
+        path: PathBuf,
+        source: io::Error,
+    },
+    ArchiveMember {
+        path: PathBuf,
+        member: Option<String>,
+    },
+    BadMagicNumber,
+    ShortHeader,
+    ChecksumMismatch {
+        expected: u16,
+        actual: u16,
+    },
+    VariableBlockRead {
+        source: io::Error,
+    },
+    SizeMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    InvalidWindow {
+        start: u64,
+        end: u64,
+        size: u32,
+    },
+    UnknownStep {
+        step: StepSelector,
+        available: Vec<String>,
+    },
+    InvalidFilter {
+        filter: String,
+        granularity: u16,
+    },
+    InvalidRate {
+        from: u16,
+        to: u16,
+    },
+    InvalidDate {
+        year: i32,
+        month: u32,
+        day: u32,
+    },
+    InvalidTime {
+        hour: u32,
+        min: u32,
+        sec: u32,
+    },
+    InvalidStepCount {
+        steps: u16,
+        max: u16,
+    },
+    SamplesTooLarge {
+        bytes: u64,
+    },
+    ChunkRead {
+        onset: u32,
+        offset: u32,
+        source: io::Error,
+    },
+    OutputWrite {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Unrecognised {
+        tried: Vec<String>,
+    },
+}
+
+impl RecipeError {
+    /// Whether the parser simply did not recognise the input, so the next
+    /// registered parser may still accept it.
+    #[must_use]
+    pub fn is_unrecognised(&self) -> bool {
+        matches!(self, Self::BadMagicNumber | Self::Unrecognised { .. })
+    }
+}
+
+impl fmt::Display for RecipeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Open { path, source } => {
+                write!(f, "could not open {}: {source}", path.display())
+            }
+            Self::ArchiveMember {
+                path,
+                member: Some(member),
+            } => write!(f, "{} has no member {member}", path.display()),
+            Self::ArchiveMember { path, member: None } => write!(
+                f,
+                "{} does not hold exactly one file; name one as {}#<member>",
+                path.display(),
+                path.display()
+            ),
+            Self::BadMagicNumber => write!(f, "bad magic number"),
+            Self::ShortHeader => write!(f, "header is shorter than expected"),
+            Self::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "header checksum mismatch: expected {expected:#06x}, got {actual:#06x}"
+            ),
+            Self::VariableBlockRead { source } => {
+                write!(f, "could not read variable-length block: {source}")
+            }
+            Self::SizeMismatch { expected, actual } => write!(
+                f,
+                "file holds {actual} bytes but its header declares {expected}"
+            ),
+            Self::InvalidWindow { start, end, size } => write!(
+                f,
+                "window {start}..{end} holds none of the {size} samples of the recording"
+            ),
+            Self::UnknownStep { step, available } => write!(
+                f,
+                "recording has no step {step} (steps are {})",
+                available.join(", ")
+            ),
+            Self::InvalidFilter {
+                filter,
+                granularity,
+            } => write!(
+                f,
+                "cannot apply {filter} to a recording sampled at {granularity} Hz"
+            ),
+            Self::InvalidRate { from, to } => {
+                write!(f, "cannot resample from {from} Hz to {to} Hz")
+            }
+            Self::InvalidDate { year, month, day } => {
+                write!(f, "invalid date {year:04}-{month:02}-{day:02}")
+            }
+            Self::InvalidTime { hour, min, sec } => {
+                write!(f, "invalid time {hour:02}:{min:02}:{sec:02}")
+            }
+            Self::InvalidStepCount { steps, max } => {
+                write!(
+                    f,
+                    "header declares {steps} steps (a recording has 1 to {max})"
+                )
+            }
+            Self::SamplesTooLarge { bytes } => write!(
+                f,
+                "recording declares {bytes} bytes of samples, more than the {} that can be read",
+                u32::MAX
+            ),
+            Self::ChunkRead {
+                onset,
+                offset,
+                source,
+            } => write!(f, "could not read chunk {onset}..{offset}: {source}"),
+            Self::OutputWrite { path, source } => {
+                write!(f, "could not write {}: {source}", path.display())
+            }
+            Self::Unrecognised { tried } if tried.is_empty() => {
+                write!(f, "no parser recognised this file (none registered)")
+            }
+            Self::Unrecognised { tried } => write!(
+                f,
+                "no parser recognised this file (tried {})",
+                tried.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RecipeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Open { source, .. }
+            | Self::VariableBlockRead { source }
+            | Self::ChunkRead { source, .. }
+            | Self::OutputWrite { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// How sure a parser is that it can read a file, judged from its first bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    None,
+    Possible,
+    Certain,
+}
+
+/// Bytes from the start of a file handed to [`Recipe::probe`].
+pub const PROBE_BYTES: usize = 4096;
+
+pub trait Recipe: Send + Sync {
+    /// Judges from up to [`PROBE_BYTES`] leading bytes, fewer for a shorter
+    /// file, whether [`Recipe::parse`] will read the file.
+    fn probe(&self, head: &[u8]) -> Confidence;
+    /// Writes into [`ParseRecipe::output_dir`], which the handler has created
+    /// and removes again if parsing fails, passing the samples through
+    /// `pipeline`; the handler writes what its detector found.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::BadMagicNumber`] when the file is not in this
+    /// recipe's format, or another [`RecipeError`] when it is but cannot be
+    /// parsed.
+    fn parse(
+        &self,
+        command: &Command<ParseRecipe>,
+        pipeline: &mut Pipeline,
+    ) -> Result<Event<RecipeParsed>, RecipeError>;
     fn identifier(&self) -> String;
 }
 
 #[derive(Default)]
 pub struct ParseRecipeCommandHandler {
     parsers: Vec<Box<dyn Recipe>>,
+    beats_detected: Option<Sender<Event<BeatsDetected>>>,
+    rhythm_reported: Option<Sender<Event<RhythmReported>>>,
+}
+
+/// Sends `payload`, if there is one, to `events`, if anyone listens.
+fn send<T>(events: Option<&Sender<Event<T>>>, payload: Option<T>) {
+    if let (Some(events), Some(payload)) = (events, payload) {
+        events
+            .send(Event {
+                event_type: 0,
+                payload,
+            })
+            .ok();
+    }
 }
 
 impl ParseRecipeCommandHandler {
+    /// Identifiers of the registered parsers, in the order they were added.
+    #[must_use]
+    pub fn identifiers(&self) -> Vec<String> {
+        self.parsers.iter().map(|p| p.identifier()).collect()
+    }
+
     pub fn register(&mut self, parser: Box<dyn Recipe>) {
         if !self
             .parsers
@@ -41,17 +417,95 @@ impl ParseRecipeCommandHandler {
             self.parsers.push(parser);
         }
     }
+
+    /// Sends an [`Event<BeatsDetected>`] for every recording whose beats are
+    /// written, from whichever thread parsed it.
+    pub fn on_beats_detected(&mut self, events: Sender<Event<BeatsDetected>>) {
+        self.beats_detected = Some(events);
+    }
+
+    /// Sends an [`Event<RhythmReported>`] for every recording whose rhythm
+    /// report is written, from whichever thread parsed it.
+    pub fn on_rhythm_reported(&mut self, events: Sender<Event<RhythmReported>>) {
+        self.rhythm_reported = Some(events);
+    }
+
+    /// Writes the beats the detector of `pipeline` found, if it had one, and
+    /// their rhythm report when one is asked for.
+    fn store_beats(
+        command: &ParseRecipe,
+        dir: &Path,
+        pipeline: &mut Pipeline,
+    ) -> Result<(Option<BeatsDetected>, Option<RhythmReported>), RecipeError> {
+        let Some(beats) = pipeline.beats() else {
+            return Ok((None, None));
+        };
+        let path = dir.join(beats::FILENAME);
+        metadata::store(&beats, &path).map_err(output_error(&path))?;
+        let reported = match command.options.rhythm {
+            Some(thresholds) => {
+                let rhythm = Rhythm::new(&beats, thresholds);
+                let path = dir.join(rhythm::FILENAME);
+                metadata::store(&rhythm, &path).map_err(output_error(&path))?;
+                Some(RhythmReported {
+                    output: path,
+                    step: rhythm.step,
+                    minutes: rhythm.minutes.len(),
+                    episodes: rhythm.episodes.len(),
+                })
+            }
+            None => None,
+        };
+        let detected = BeatsDetected {
+            output: path,
+            step: beats.step,
+            beats: beats.beats.len(),
+        };
+        Ok((Some(detected), reported))
+    }
 }
 
 impl CommandHandler<ParseRecipe, RecipeParsed> for ParseRecipeCommandHandler {
-    fn handle(&self, command: &command::Command<ParseRecipe>) -> Option<Event<RecipeParsed>> {
-        for parser in &self.parsers {
-            let result = parser.parse(command);
-            if result.is_some() {
-                return result;
+    type Error = RecipeError;
+
+    fn handle(
+        &self,
+        command: &command::Command<ParseRecipe>,
+    ) -> Result<Event<RecipeParsed>, RecipeError> {
+        let head = source::Source::head(&command.payload.filepath, PROBE_BYTES)?;
+        let mut candidates: Vec<(Confidence, &dyn Recipe)> = self
+            .parsers
+            .iter()
+            .map(|p| (p.probe(&head), p.as_ref()))
+            .filter(|(confidence, _)| *confidence > Confidence::None)
+            .collect();
+        candidates.sort_by_key(|(confidence, _)| std::cmp::Reverse(*confidence));
+        let dir = command.payload.output_dir();
+        for (_, parser) in candidates {
+            fs::create_dir(&dir).map_err(output_error(&dir))?;
+            let mut pipeline = command.payload.pipeline();
+            let parsed = parser.parse(command, &mut pipeline).and_then(|event| {
+                Self::store_beats(&command.payload, &dir, &mut pipeline).map(|beats| (event, beats))
+            });
+            match parsed {
+                Ok((event, (beats, rhythm))) => {
+                    send(self.beats_detected.as_ref(), beats);
+                    send(self.rhythm_reported.as_ref(), rhythm);
+                    return Ok(event);
+                }
+                Err(err) => {
+                    if let Err(e) = fs::remove_dir_all(&dir) {
+                        warn!("Could not remove {}: {e}", dir.display());
+                    }
+                    if !err.is_unrecognised() {
+                        return Err(err);
+                    }
+                }
             }
         }
-        None
+        Err(RecipeError::Unrecognised {
+            tried: self.identifiers(),
+        })
     }
 }
 
@@ -72,3 +526,216 @@ impl EventHandler<RecipeParsed> for RecipeParsedEventHandler {
         ));
     }
 }
+
+pub struct BeatsDetectedEventHandler {
+    pub notifier: Rc<dyn crate::notifier::Notifier>,
+}
+
+impl EventHandler<BeatsDetected> for BeatsDetectedEventHandler {
+    fn handle(&self, event: Event<BeatsDetected>) {
+        let beats = event.payload;
+        self.notifier.success(format!(
+            "{} beats on {}: {}",
+            beats.beats,
+            beats.step,
+            beats.output.display()
+        ));
+    }
+}
+
+pub struct RhythmReportedEventHandler {
+    pub notifier: Rc<dyn crate::notifier::Notifier>,
+}
+
+impl EventHandler<RhythmReported> for RhythmReportedEventHandler {
+    fn handle(&self, event: Event<RhythmReported>) {
+        let rhythm = event.payload;
+        self.notifier.success(format!(
+            "{} bradycardia or tachycardia episodes on {}: {}",
+            rhythm.episodes,
+            rhythm.step,
+            rhythm.output.display()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use tempfile::{tempdir, NamedTempFile, TempDir};
+
+    struct Unrecognising;
+
+    impl Recipe for Unrecognising {
+        fn probe(&self, _head: &[u8]) -> Confidence {
+            Confidence::Certain
+        }
+
+        fn parse(
+            &self,
+            _command: &Command<ParseRecipe>,
+            _pipeline: &mut Pipeline,
+        ) -> Result<Event<RecipeParsed>, RecipeError> {
+            Err(RecipeError::BadMagicNumber)
+        }
+
+        fn identifier(&self) -> String {
+            String::from("unrecognising")
+        }
+    }
+
+    struct Failing;
+
+    impl Recipe for Failing {
+        fn probe(&self, _head: &[u8]) -> Confidence {
+            Confidence::Possible
+        }
+
+        fn parse(
+            &self,
+            _command: &Command<ParseRecipe>,
+            _pipeline: &mut Pipeline,
+        ) -> Result<Event<RecipeParsed>, RecipeError> {
+            Err(RecipeError::ShortHeader)
+        }
+
+        fn identifier(&self) -> String {
+            String::from("failing")
+        }
+    }
+
+    struct Picky;
+
+    impl Recipe for Picky {
+        fn probe(&self, head: &[u8]) -> Confidence {
+            if head.starts_with(b"PICKY") {
+                Confidence::Certain
+            } else {
+                Confidence::None
+            }
+        }
+
+        fn parse(
+            &self,
+            _command: &Command<ParseRecipe>,
+            _pipeline: &mut Pipeline,
+        ) -> Result<Event<RecipeParsed>, RecipeError> {
+            Ok(Event {
+                event_type: 0,
+                payload: RecipeParsed {
+                    output: PathBuf::from("picky"),
+                },
+            })
+        }
+
+        fn identifier(&self) -> String {
+            String::from("picky")
+        }
+    }
+
+    fn command(dir: &TempDir, file: &NamedTempFile) -> Command<ParseRecipe> {
+        Command {
+            command_type: 0,
+            payload: ParseRecipe {
+                basepath: dir.path().to_str().unwrap().into(),
+                filepath: file.path().to_str().unwrap().into(),
+                identifier: uuid::Uuid::new_v4(),
+                ..ParseRecipe::default()
+            },
+        }
+    }
+
+    fn file(bytes: &[u8]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(bytes).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_given_index_or_time_when_window_bound_from_str_then_parsed() {
+        assert_eq!("72000".parse(), Ok(WindowBound::Sample(72000)));
+        let time = chrono::NaiveDate::from_ymd_opt(2008, 11, 5)
+            .unwrap()
+            .and_hms_milli_opt(5, 15, 0, 500)
+            .unwrap();
+        assert_eq!("2008-11-05T05:15:00.5".parse(), Ok(WindowBound::Time(time)));
+        assert_eq!(
+            "2008-11-05 05:15:00.500".parse(),
+            Ok(WindowBound::Time(time))
+        );
+        assert!("yesterday".parse::<WindowBound>().is_err());
+    }
+
+    #[test]
+    fn test_given_index_or_name_when_step_selector_from_str_then_parsed() {
+        assert_eq!("2".parse(), Ok(StepSelector::Index(2)));
+        assert_eq!(" V1 ".parse(), Ok(StepSelector::Name(String::from("V1"))));
+        assert!("".parse::<StepSelector>().is_err());
+    }
+
+    #[test]
+    fn test_given_no_parsers_when_handle_then_unrecognised() {
+        let handler = ParseRecipeCommandHandler::default();
+        assert!(matches!(
+            handler.handle(&command(&tempdir().unwrap(), &file(b"data"))),
+            Err(RecipeError::Unrecognised { tried }) if tried.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_given_unrecognising_parser_when_handle_then_next_parser_used() {
+        let mut handler = ParseRecipeCommandHandler::default();
+        handler.register(Box::new(Unrecognising));
+        handler.register(Box::new(null::Null {}));
+        let dir = tempdir().unwrap();
+        let file = file(b"data");
+        let cmd = command(&dir, &file);
+        assert!(handler.handle(&cmd).is_ok());
+        assert!(cmd.payload.output_dir().is_dir());
+    }
+
+    #[test]
+    fn test_given_failing_parser_when_handle_then_error_returned() {
+        let mut handler = ParseRecipeCommandHandler::default();
+        handler.register(Box::new(Failing));
+        handler.register(Box::new(null::Null {}));
+        let dir = tempdir().unwrap();
+        let file = file(b"data");
+        let cmd = command(&dir, &file);
+        assert!(matches!(
+            handler.handle(&cmd),
+            Err(RecipeError::ShortHeader)
+        ));
+        assert!(!cmd.payload.output_dir().exists());
+    }
+
+    #[test]
+    fn test_given_certain_parser_when_handle_then_preferred_over_earlier_possible_one() {
+        let mut handler = ParseRecipeCommandHandler::default();
+        handler.register(Box::new(null::Null {}));
+        handler.register(Box::new(Picky));
+        let dir = tempdir().unwrap();
+        let file = file(b"PICKY data");
+        let cmd = command(&dir, &file);
+        let event = handler.handle(&cmd).unwrap();
+        assert_eq!(event.payload.output, PathBuf::from("picky"));
+        assert!(cmd.payload.output_dir().is_dir());
+    }
+
+    #[test]
+    fn test_given_no_parser_probes_file_when_handle_then_tried_identifiers_listed() {
+        let mut handler = ParseRecipeCommandHandler::default();
+        handler.register(Box::new(Picky));
+        handler.register(Box::new(carne_asade::CarneAsada {}));
+        let err = handler
+            .handle(&command(&tempdir().unwrap(), &file(b"data")))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no parser recognised this file (tried picky, carne_asada)"
+        );
+    }
+}
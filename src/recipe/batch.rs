@@ -0,0 +1,305 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::command::{Command, CommandHandler};
+use crate::event::{Event, EventHandler};
+
+use super::{ParseOptions, ParseRecipe, ParseRecipeCommandHandler, RecipeError};
+
+/// Marks a manifest, as in `@nightly.txt`: one recording per line, blank
+/// lines and `#` comments skipped, relative paths taken from the manifest's
+/// directory.
+const MANIFEST_PREFIX: char = '@';
+
+#[derive(Debug, Default)]
+pub struct ParseBatch {
+    pub basepath: String,
+    /// A directory, a glob such as `holters/*.zip`, a `@manifest`, or a
+    /// single recording.
+    pub inputs: String,
+    /// Files parsed at the same time; at least one.
+    pub concurrency: usize,
+    /// Given to every file's [`ParseRecipe`] as is.
+    pub options: ParseOptions,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ParseOutcome {
+    pub filepath: String,
+    pub identifier: uuid::Uuid,
+    /// The output path, or why the file could not be parsed.
+    pub result: Result<PathBuf, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchParsed {
+    pub outcomes: Vec<ParseOutcome>,
+}
+
+impl BatchParsed {
+    #[must_use]
+    pub fn failures(&self) -> usize {
+        self.outcomes.iter().filter(|o| o.result.is_err()).count()
+    }
+}
+
+/// Recordings named by `inputs`: a manifest's in its own order, a
+/// directory's files or a glob's matches sorted by path.
+///
+/// # Errors
+///
+/// Returns [`RecipeError::Open`] when the directory or manifest cannot be
+/// read, the glob is malformed, or nothing matches.
+pub fn resolve(inputs: &str) -> Result<Vec<String>, RecipeError> {
+    let open_error = |source| RecipeError::Open {
+        path: PathBuf::from(inputs),
+        source,
+    };
+    let paths: Vec<PathBuf> = if let Some(manifest) = inputs.strip_prefix(MANIFEST_PREFIX) {
+        let manifest = Path::new(manifest);
+        let dir = manifest.parent().unwrap_or_else(|| Path::new(""));
+        fs::read_to_string(manifest)
+            .map_err(|source| RecipeError::Open {
+                path: manifest.to_path_buf(),
+                source,
+            })?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| dir.join(line))
+            .collect()
+    } else if Path::new(inputs).is_dir() {
+        let mut paths: Vec<PathBuf> = fs::read_dir(inputs)
+            .and_then(|entries| entries.map(|e| e.map(|e| e.path())).collect())
+            .map_err(open_error)?;
+        paths.retain(|path| path.is_file());
+        paths.sort();
+        paths
+    } else if inputs.contains(['*', '?', '[']) {
+        glob::glob(inputs)
+            .map_err(|e| open_error(io::Error::new(io::ErrorKind::InvalidInput, e.msg)))?
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .collect()
+    } else {
+        vec![PathBuf::from(inputs)]
+    };
+    if paths.is_empty() {
+        return Err(open_error(io::Error::from(io::ErrorKind::NotFound)));
+    }
+    Ok(paths
+        .into_iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Parses each recording of a batch through a [`ParseRecipeCommandHandler`],
+/// a few files at a time, carrying on past files that fail.
+pub struct ParseBatchCommandHandler {
+    pub recipes: ParseRecipeCommandHandler,
+}
+
+impl CommandHandler<ParseBatch, BatchParsed> for ParseBatchCommandHandler {
+    type Error = RecipeError;
+
+    fn handle(&self, command: &Command<ParseBatch>) -> Result<Event<BatchParsed>, RecipeError> {
+        let batch = &command.payload;
+        let filepaths = resolve(&batch.inputs)?;
+        let next = AtomicUsize::new(0);
+        let outcomes: Mutex<Vec<Option<ParseOutcome>>> =
+            Mutex::new(filepaths.iter().map(|_| None).collect());
+        thread::scope(|scope| {
+            for _ in 0..batch.concurrency.clamp(1, filepaths.len().max(1)) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(filepath) = filepaths.get(index) else {
+                        break;
+                    };
+                    let recipe = Command {
+                        command_type: command.command_type,
+                        payload: ParseRecipe {
+                            basepath: batch.basepath.clone(),
+                            filepath: filepath.clone(),
+                            identifier: uuid::Uuid::new_v4(),
+                            options: batch.options.clone(),
+                        },
+                    };
+                    let outcome = ParseOutcome {
+                        filepath: filepath.clone(),
+                        identifier: recipe.payload.identifier,
+                        result: self
+                            .recipes
+                            .handle(&recipe)
+                            .map(|event| event.payload.output)
+                            .map_err(|err| err.to_string()),
+                    };
+                    outcomes.lock().unwrap_or_else(PoisonError::into_inner)[index] = Some(outcome);
+                });
+            }
+        });
+        let outcomes = outcomes
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+            .into_iter()
+            .flatten()
+            .collect();
+        Ok(Event {
+            event_type: 0,
+            payload: BatchParsed { outcomes },
+        })
+    }
+}
+
+/// Reports every file of a batch, then a summary when there is more than one.
+pub struct BatchParsedEventHandler {
+    pub notifier: Rc<dyn crate::notifier::Notifier>,
+}
+
+impl EventHandler<BatchParsed> for BatchParsedEventHandler {
+    fn handle(&self, event: Event<BatchParsed>) {
+        let batch = event.payload;
+        for outcome in &batch.outcomes {
+            match &outcome.result {
+                Ok(output) => {
+                    self.notifier
+                        .success(format!("{}: {}", outcome.filepath, output.display()))
+                }
+                Err(err) => self
+                    .notifier
+                    .failure(format!("{}: {err}", outcome.filepath)),
+            }
+        }
+        if batch.outcomes.len() > 1 {
+            let summary = format!(
+                "{} of {} files parsed",
+                batch.outcomes.len() - batch.failures(),
+                batch.outcomes.len()
+            );
+            if batch.failures() == 0 {
+                self.notifier.success(summary);
+            } else {
+                self.notifier.failure(summary);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::recipe::carne_asade::{fixture, CarneAsada};
+
+    #[derive(Default)]
+    struct Recording {
+        messages: RefCell<Vec<String>>,
+    }
+
+    impl crate::notifier::Notifier for Recording {
+        fn success(&self, msg: String) {
+            self.messages.borrow_mut().push(format!("ok {msg}"));
+        }
+
+        fn failure(&self, msg: String) {
+            self.messages.borrow_mut().push(format!("failed {msg}"));
+        }
+    }
+
+    #[test]
+    fn test_given_directory_glob_or_manifest_when_resolve_then_recordings_listed() {
+        let dir = tempdir().unwrap();
+        for name in ["b.dat", "a.dat", "notes.txt"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        fs::write(
+            dir.path().join("nightly.list"),
+            "# tonight\nb.dat\n\n/elsewhere/c.dat\n",
+        )
+        .unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+
+        assert_eq!(
+            resolve(&path("")).unwrap(),
+            vec![
+                path("a.dat"),
+                path("b.dat"),
+                path("nightly.list"),
+                path("notes.txt")
+            ]
+        );
+        assert_eq!(
+            resolve(&path("*.dat")).unwrap(),
+            vec![path("a.dat"), path("b.dat")]
+        );
+        assert_eq!(
+            resolve(&format!("@{}", path("nightly.list"))).unwrap(),
+            vec![path("b.dat"), String::from("/elsewhere/c.dat")]
+        );
+        assert_eq!(resolve(&path("a.dat")).unwrap(), vec![path("a.dat")]);
+        assert!(matches!(
+            resolve(&path("*.edf")),
+            Err(RecipeError::Open { .. })
+        ));
+    }
+
+    #[test]
+    fn test_given_batch_with_bad_file_when_handle_then_others_parsed_and_failure_reported() {
+        let inputs = tempdir().unwrap();
+        let recording = fixture::recording(200, &[(5, 1000)], &[vec![1, 2, 3]]);
+        for name in ["1.dat", "2.dat", "4.dat"] {
+            fs::write(inputs.path().join(name), &recording).unwrap();
+        }
+        fs::write(inputs.path().join("3.dat"), b"TORTILLA").unwrap();
+        let output = tempdir().unwrap();
+        let mut recipes = ParseRecipeCommandHandler::default();
+        recipes.register(Box::new(CarneAsada {}));
+        let handler = ParseBatchCommandHandler { recipes };
+        let event = handler
+            .handle(&Command {
+                command_type: 0,
+                payload: ParseBatch {
+                    basepath: output.path().to_str().unwrap().into(),
+                    inputs: inputs.path().to_str().unwrap().into(),
+                    concurrency: 2,
+                    ..ParseBatch::default()
+                },
+            })
+            .unwrap();
+
+        let outcomes = &event.payload.outcomes;
+        assert_eq!(
+            outcomes
+                .iter()
+                .map(|o| Path::new(&o.filepath).file_name().unwrap().to_owned())
+                .collect::<Vec<_>>(),
+            ["1.dat", "2.dat", "3.dat", "4.dat"]
+        );
+        assert_eq!(event.payload.failures(), 1);
+        assert!(outcomes[2].result.is_err());
+        for outcome in [&outcomes[0], &outcomes[1], &outcomes[3]] {
+            assert!(outcome.result.as_ref().unwrap().is_file());
+        }
+        assert_eq!(fs::read_dir(output.path()).unwrap().count(), 3);
+
+        let notifier = Rc::new(Recording::default());
+        BatchParsedEventHandler {
+            notifier: notifier.clone(),
+        }
+        .handle(event);
+        let messages = notifier.messages.borrow();
+        assert_eq!(messages.len(), 5);
+        assert!(messages[2].starts_with("failed ") && messages[2].contains("3.dat"));
+        assert_eq!(messages[4], "failed 3 of 4 files parsed");
+    }
+}
@@ -1,25 +1,36 @@
+pub mod generator;
+pub mod inspect;
+pub mod stream;
+pub mod writer;
+
+use crate::encoder::output_error;
+use crate::stage::Pipeline;
 use crate::{command::Command, event::Event, metadata};
 
-use std::cmp::Ordering;
-use std::io::{Seek, SeekFrom, Write};
-use std::path::Path;
+use log::warn;
+
+use std::io::{self, Seek, SeekFrom};
 use std::thread;
 use std::{
-    fs::{self, File},
-    io::{BufReader, BufWriter, Read},
+    fs::File,
+    io::{BufReader, Read},
 };
 
-use super::{ParseRecipe, Recipe, RecipeParsed};
+use super::source::Source;
+use super::{
+    Confidence, ParseRecipe, Recipe, RecipeError, RecipeParsed, StepSelector, WindowBound,
+};
 
-const DELIMITER: &str = ",";
-const FILENAME: &str = "recipe.json";
 const METADATA_FILENAME: &str = "metadata.json";
-const GUACAMOLE_START_SLICE: &str = ",\"guacamole\":[[";
-const GUACAMOLE_END_SLICE: &str = "]]}";
 const CARNE_ASADA_MAGIC_NUMBER: &str = "CARNE1.0";
 const MAX_THREADS: u32 = 8;
 const MAX_BYTES: u32 = 102_400;
 const DTYPE: u32 = 2;
+/// Steps a CARNE1.0 header has room to describe.
+const MAX_STEPS: u16 = 12;
+const HEADER_END: u32 = 522;
+/// Stored by recorders that do not compute a header checksum at all.
+const NO_CHECKSUM: u16 = 0x0000;
 
 const STEPS_BY_NAME: [&str; 20] = [
     "Unknown",
@@ -44,59 +55,162 @@ const STEPS_BY_NAME: [&str; 20] = [
     "AI",
 ];
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+/// How the checksum stored after the magic number compares to the header.
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Checksum {
+    Valid,
+    Mismatch,
+    /// Stored as `0x0000`, by recorders that do not compute one.
+    Absent,
+}
+
+impl Checksum {
+    /// Compares `stored`, as read from the file, with the checksum of `header`.
+    #[must_use]
+    pub fn of(stored: u16, header: &[u8; 512]) -> Self {
+        if stored == NO_CHECKSUM {
+            Self::Absent
+        } else if stored == Header::checksum(header) {
+            Self::Valid
+        } else {
+            Self::Mismatch
+        }
+    }
+}
+
+impl std::fmt::Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Valid => "valid",
+            Self::Mismatch => "mismatch",
+            Self::Absent => "absent",
+        })
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
 pub struct Header {
-    size: u32,
-    date_of_recipe: chrono::NaiveDate,
-    time_of_recipe: chrono::NaiveTime,
-    number_of_steps: u16,
-    steps: [usize; 12],
-    unit_conversion: [i32; 12],
-    granularity: u16,
+    pub variable_block_size: u32,
+    pub size: u32,
+    pub variable_block_offset: u32,
+    pub sample_offset: u32,
+    pub file_version: i16,
+    pub patient: metadata::Patient,
+    pub date_of_recipe: chrono::NaiveDate,
+    pub date_of_file: Option<chrono::NaiveDate>,
+    pub time_of_recipe: chrono::NaiveTime,
+    pub number_of_steps: u16,
+    pub steps: [usize; 12],
+    pub step_quality: [i16; 12],
+    pub unit_conversion: [i32; 12],
+    pub pacemaker: metadata::Pacemaker,
+    pub recorder: String,
+    pub granularity: u16,
+    pub proprietary: String,
+    pub copyright: String,
+}
+
+fn read_u16(buffer: &[u8; 512], at: usize) -> u16 {
+    u16::from_le_bytes([buffer[at], buffer[at + 1]])
+}
+
+fn read_i16(buffer: &[u8; 512], at: usize) -> i16 {
+    i16::from_le_bytes([buffer[at], buffer[at + 1]])
+}
+
+fn read_u32(buffer: &[u8; 512], at: usize) -> u32 {
+    u32::from_le_bytes([buffer[at], buffer[at + 1], buffer[at + 2], buffer[at + 3]])
+}
+
+fn read_string(buffer: &[u8; 512], at: usize, len: usize) -> String {
+    let field = &buffer[at..at + len];
+    let end = field.iter().position(|b| *b == 0).unwrap_or(len);
+    String::from_utf8_lossy(&field[..end]).trim().to_string()
+}
+
+fn read_optional_date(buffer: &[u8; 512], at: usize) -> Option<chrono::NaiveDate> {
+    chrono::NaiveDate::from_ymd_opt(
+        i32::from(read_i16(buffer, at + 4)),
+        u32::from(read_u16(buffer, at + 2)),
+        u32::from(read_u16(buffer, at)),
+    )
+}
+
+fn crc_ccitt(buffer: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in buffer {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 == 0 {
+                crc <<= 1;
+            } else {
+                crc = (crc << 1) ^ 0x1021;
+            }
+        }
+    }
+    crc
 }
 
 impl Header {
+    /// CRC-CCITT of the header, as stored right after the magic number.
     #[must_use]
-    pub fn parse_date_of_recipe(buffer: &[u8; 512]) -> chrono::NaiveDate {
-        let day: u32 = u32::from(u16::from_le_bytes(
-            buffer[128..130].try_into().expect("Could not parse day."),
-        ));
-        let month: u32 = u32::from(u16::from_le_bytes(
-            buffer[130..132].try_into().expect("Could not parse month."),
-        ));
-        let year: i32 = i32::from(u16::from_le_bytes(
-            buffer[132..134].try_into().expect("Could not parse year."),
-        ));
-        chrono::NaiveDate::from_ymd_opt(year, month, day).expect("Could not parse date.")
+    pub fn checksum(buffer: &[u8; 512]) -> u16 {
+        crc_ccitt(buffer)
     }
 
-    #[must_use]
-    pub fn parse_time_of_recipe(buffer: &[u8; 512]) -> chrono::NaiveTime {
-        let hour: u32 = u32::from(u16::from_le_bytes(
-            buffer[140..142].try_into().expect("Could not parse hour."),
-        ));
-        let min: u32 = u32::from(u16::from_le_bytes(
-            buffer[142..144].try_into().expect("Could not parse min."),
-        ));
-        let sec: u32 = u32::from(u16::from_le_bytes(
-            buffer[144..146].try_into().expect("Could not parse sec."),
-        ));
-        chrono::NaiveTime::from_hms_opt(hour, min, sec).expect("Could not parse time.")
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::InvalidDate`] when the stored date does not exist.
+    pub fn parse_date_of_recipe(buffer: &[u8; 512]) -> Result<chrono::NaiveDate, RecipeError> {
+        let day: u32 = u32::from(read_u16(buffer, 128));
+        let month: u32 = u32::from(read_u16(buffer, 130));
+        let year: i32 = i32::from(read_u16(buffer, 132));
+        chrono::NaiveDate::from_ymd_opt(year, month, day).ok_or(RecipeError::InvalidDate {
+            year,
+            month,
+            day,
+        })
+    }
+
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::InvalidTime`] when the stored time does not exist.
+    pub fn parse_time_of_recipe(buffer: &[u8; 512]) -> Result<chrono::NaiveTime, RecipeError> {
+        let hour: u32 = u32::from(read_u16(buffer, 140));
+        let min: u32 = u32::from(read_u16(buffer, 142));
+        let sec: u32 = u32::from(read_u16(buffer, 144));
+        chrono::NaiveTime::from_hms_opt(hour, min, sec).ok_or(RecipeError::InvalidTime {
+            hour,
+            min,
+            sec,
+        })
+    }
+
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::InvalidStepCount`] when the header declares no
+    /// steps, more than it can describe, or frames too large to read.
+    pub fn parse_number_of_steps(buffer: &[u8; 512]) -> Result<u16, RecipeError> {
+        let steps = read_u16(buffer, 146);
+        if steps == 0 || steps > MAX_STEPS || u32::from(steps) * DTYPE > MAX_BYTES {
+            return Err(RecipeError::InvalidStepCount {
+                steps,
+                max: MAX_STEPS,
+            });
+        }
+        Ok(steps)
     }
 
     #[must_use]
     pub fn parse_steps(buffer: &[u8; 512]) -> [usize; 12] {
         let mut steps: [usize; 12] = [0; 12];
-        for i in 0..steps.len() {
-            let val = usize::from(u16::from_le_bytes(
-                buffer[(148 + i * 2)..(148 + (i * 2) + 2)]
-                    .try_into()
-                    .expect("Could not create step."),
-            ));
+        for (i, step) in steps.iter_mut().enumerate() {
+            let val = usize::from(read_u16(buffer, 148 + i * 2));
             if val < STEPS_BY_NAME.len() {
-                steps[i] = val;
+                *step = val;
             } else {
-                steps[i] = 0;
+                *step = 0;
             }
         }
         steps
@@ -105,33 +219,302 @@ impl Header {
     #[must_use]
     pub fn parse_unit_conversion(buffer: &[u8; 512]) -> [i32; 12] {
         let mut conversions = [-9_i32; 12];
-        for i in 0..conversions.len() {
-            conversions[i] = i32::from(i16::from_le_bytes(
-                buffer[(196 + (i * 2))..(196 + (i * 2) + 2)]
-                    .try_into()
-                    .expect("Could not create conversion."),
-            ));
+        for (i, conversion) in conversions.iter_mut().enumerate() {
+            *conversion = i32::from(read_i16(buffer, 196 + i * 2));
         }
         conversions
     }
 
     #[must_use]
-    pub fn parse(buffer: &[u8; 512]) -> Self {
-        Self {
-            size: u32::from_le_bytes(buffer[4..8].try_into().expect("Could not create size.")),
-            date_of_recipe: Self::parse_date_of_recipe(buffer),
-            time_of_recipe: Self::parse_time_of_recipe(buffer),
-            number_of_steps: (u16::from(buffer[146])) + (u16::from(buffer[147]) << 8),
+    pub fn parse_step_quality(buffer: &[u8; 512]) -> [i16; 12] {
+        let mut quality = [0_i16; 12];
+        for (i, q) in quality.iter_mut().enumerate() {
+            *q = read_i16(buffer, 172 + i * 2);
+        }
+        quality
+    }
+
+    #[must_use]
+    pub fn parse_patient(buffer: &[u8; 512]) -> metadata::Patient {
+        metadata::Patient {
+            first_name: read_string(buffer, 18, 40),
+            last_name: read_string(buffer, 58, 40),
+            id: read_string(buffer, 98, 20),
+            sex: metadata::Sex::from(read_u16(buffer, 118)),
+            race: metadata::Race::from(read_u16(buffer, 120)),
+            date_of_birth: read_optional_date(buffer, 122),
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Returns a [`RecipeError`] when the stored date, time or number of steps
+    /// is invalid.
+    pub fn parse(buffer: &[u8; 512]) -> Result<Self, RecipeError> {
+        Ok(Self {
+            variable_block_size: read_u32(buffer, 0),
+            size: read_u32(buffer, 4),
+            variable_block_offset: read_u32(buffer, 8),
+            sample_offset: read_u32(buffer, 12),
+            file_version: read_i16(buffer, 16),
+            patient: Self::parse_patient(buffer),
+            date_of_recipe: Self::parse_date_of_recipe(buffer)?,
+            date_of_file: read_optional_date(buffer, 134),
+            time_of_recipe: Self::parse_time_of_recipe(buffer)?,
+            number_of_steps: Self::parse_number_of_steps(buffer)?,
             steps: Self::parse_steps(buffer),
+            step_quality: Self::parse_step_quality(buffer),
             unit_conversion: Self::parse_unit_conversion(buffer),
-            granularity: u16::from(buffer[262]) + (u16::from(buffer[263]) << 8),
+            pacemaker: metadata::Pacemaker::from(read_u16(buffer, 220)),
+            recorder: read_string(buffer, 222, 40),
+            granularity: read_u16(buffer, 262),
+            proprietary: read_string(buffer, 264, 80),
+            copyright: read_string(buffer, 344, 80),
+        })
+    }
+
+    /// Offset of the variable-length block, falling back to the end of the
+    /// fixed header when the file does not declare one.
+    #[must_use]
+    pub fn variable_block_start(&self) -> u32 {
+        if self.variable_block_offset == 0 {
+            HEADER_END
+        } else {
+            self.variable_block_offset
+        }
+    }
+
+    /// Offset of the first sample, falling back to the end of the
+    /// variable-length block when the file does not declare one.
+    #[must_use]
+    pub fn sample_start(&self) -> u32 {
+        if self.sample_offset == 0 {
+            self.variable_block_start() + self.variable_block_size
+        } else {
+            self.sample_offset
+        }
+    }
+
+    /// Bytes of samples the header declares, as chunks are planned in.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::SamplesTooLarge`] when they cannot be addressed
+    /// in 32 bits.
+    pub fn sample_bytes(&self) -> Result<u32, RecipeError> {
+        let bytes = u64::from(self.size)
+            .checked_mul(u64::from(u32::from(self.number_of_steps) * DTYPE))
+            .ok_or(RecipeError::SamplesTooLarge { bytes: u64::MAX })?;
+        u32::try_from(bytes).map_err(|_| RecipeError::SamplesTooLarge { bytes })
+    }
+
+    #[must_use]
+    pub fn start(&self) -> chrono::NaiveDateTime {
+        self.date_of_recipe.and_time(self.time_of_recipe)
+    }
+
+    /// Wall-clock time of the sample at `index`, counted per step from the
+    /// start of the recording.
+    #[must_use]
+    pub fn timestamp_of(&self, index: u64) -> chrono::NaiveDateTime {
+        if self.granularity == 0 {
+            return self.start();
+        }
+        let nanos = u128::from(index) * 1_000_000_000 / u128::from(self.granularity);
+        self.start() + chrono::Duration::nanoseconds(i64::try_from(nanos).unwrap_or(i64::MAX))
+    }
+
+    /// Index of the sample at `bound`, clamped to the recording; a time
+    /// falls on the sample at or before it.
+    #[must_use]
+    pub fn sample_of(&self, bound: WindowBound) -> u64 {
+        let sample = match bound {
+            WindowBound::Sample(sample) => sample,
+            WindowBound::Time(time) => {
+                let nanos = (time - self.start()).num_nanoseconds().unwrap_or(i64::MAX);
+                let sample = i128::from(nanos) * i128::from(self.granularity) / 1_000_000_000;
+                u64::try_from(sample.max(0)).unwrap_or(u64::MAX)
+            }
+        };
+        sample.min(u64::from(self.size))
+    }
+
+    /// Samples from `start` up to, not including, `end`; the whole recording
+    /// when both are `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::InvalidWindow`] when the window holds no
+    /// samples.
+    pub fn window(
+        &self,
+        start: Option<WindowBound>,
+        end: Option<WindowBound>,
+    ) -> Result<std::ops::Range<u64>, RecipeError> {
+        let first = start.map_or(0, |bound| self.sample_of(bound));
+        let last = end.map_or(u64::from(self.size), |bound| self.sample_of(bound));
+        if first >= last && (start.is_some() || end.is_some()) {
+            return Err(RecipeError::InvalidWindow {
+                start: first,
+                end: last,
+                size: self.size,
+            });
+        }
+        Ok(first..last)
+    }
+
+    /// Name of each step the header declares, one per unit conversion and
+    /// quality, a step without a known code included as `Unknown`.
+    #[must_use]
+    pub fn step_names(&self) -> Vec<String> {
+        (0..usize::from(self.number_of_steps).min(12))
+            .map(|i| self.step_name(i).to_string())
+            .collect()
+    }
+
+    /// Name of the step at `index`, whatever its code.
+    fn step_name(&self, index: usize) -> &'static str {
+        STEPS_BY_NAME
+            .get(self.steps[index])
+            .copied()
+            .unwrap_or(STEPS_BY_NAME[0])
+    }
+
+    /// Indices of the steps `selectors` name, in their order and without
+    /// repeats; every step when `selectors` is empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::UnknownStep`] when a name is not in the header
+    /// or an index is past its last step.
+    pub fn select_steps(&self, selectors: &[StepSelector]) -> Result<Vec<usize>, RecipeError> {
+        let count = usize::from(self.number_of_steps).min(12);
+        if selectors.is_empty() {
+            return Ok((0..count).collect());
+        }
+        let mut selected = Vec::new();
+        for selector in selectors {
+            let index = match selector {
+                StepSelector::Index(index) => Some(*index).filter(|i| *i < count),
+                StepSelector::Name(name) => {
+                    (0..count).find(|i| self.step_name(*i).eq_ignore_ascii_case(name))
+                }
+            }
+            .ok_or_else(|| RecipeError::UnknownStep {
+                step: selector.clone(),
+                available: (0..count).map(|i| self.step_name(i).to_string()).collect(),
+            })?;
+            if !selected.contains(&index) {
+                selected.push(index);
+            }
+        }
+        Ok(selected)
+    }
+}
+
+impl From<&Header> for metadata::Metadata {
+    fn from(header: &Header) -> Self {
+        Self {
+            size: header.size,
+            date_of_recipe: header.date_of_recipe,
+            time_of_recipe: header.time_of_recipe,
+            first_sample: 0,
+            number_of_steps: header.number_of_steps,
+            steps: header.step_names(),
+            units: 1.0,
+            granularity: header.granularity,
+            variable_block_size: header.variable_block_size,
+            variable_block_offset: header.variable_block_offset,
+            sample_offset: header.sample_offset,
+            file_version: header.file_version,
+            patient: header.patient.clone(),
+            date_of_file: header.date_of_file,
+            step_quality: header.step_quality[..usize::from(header.number_of_steps).min(12)]
+                .to_vec(),
+            unit_conversion: header.unit_conversion[..usize::from(header.number_of_steps).min(12)]
+                .to_vec(),
+            pacemaker: header.pacemaker,
+            recorder: header.recorder.clone(),
+            proprietary: header.proprietary.clone(),
+            copyright: header.copyright.clone(),
+            derived_steps: Vec::new(),
+            filters: Vec::new(),
+            variable_block: Vec::new(),
+            variable_block_text: None,
         }
     }
 }
 
 pub struct CarneAsada {}
 
+/// A chunk of samples as handed from a reading thread to the encoder.
+enum Guacamole {
+    Raw(Vec<u8>),
+    Decoded(Vec<Vec<f64>>),
+}
+
 impl CarneAsada {
+    /// Reads the magic number, checksum and fixed header, leaving `reader`
+    /// positioned at the end of the fixed header. When `lenient`, a checksum
+    /// that does not match, or is [`Checksum::Absent`], is only warned about.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::BadMagicNumber`] when the input is not a CARNE1.0
+    /// file, or another [`RecipeError`] when its header is damaged.
+    pub fn read_header<R: Read>(reader: &mut R, lenient: bool) -> Result<Header, RecipeError> {
+        let mut buffer: [u8; 10] = [0u8; 10];
+        reader
+            .read_exact(&mut buffer)
+            .map_err(|_| RecipeError::BadMagicNumber)?;
+        if &buffer[..8] != CARNE_ASADA_MAGIC_NUMBER.as_bytes() {
+            return Err(RecipeError::BadMagicNumber);
+        }
+        let mut header_buffer: [u8; 512] = [0u8; 512];
+        reader
+            .read_exact(&mut header_buffer)
+            .map_err(|_| RecipeError::ShortHeader)?;
+        let expected = u16::from_le_bytes([buffer[8], buffer[9]]);
+        let actual = Header::checksum(&header_buffer);
+        match Checksum::of(expected, &header_buffer) {
+            Checksum::Valid => {}
+            _ if !lenient => return Err(RecipeError::ChecksumMismatch { expected, actual }),
+            Checksum::Absent => {
+                warn!("Header has no checksum; it cannot be checked for damage.");
+            }
+            Checksum::Mismatch => {
+                warn!("Header checksum mismatch: expected {expected:#06x}, got {actual:#06x}.");
+            }
+        }
+        Header::parse(&header_buffer)
+    }
+
+    /// Checks the magic number, the header checksum and that the file holds
+    /// exactly the samples its header declares, without decoding them.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first problem found as a [`RecipeError`], such as
+    /// [`RecipeError::ChecksumMismatch`], [`RecipeError::SamplesTooLarge`] or
+    /// [`RecipeError::SizeMismatch`].
+    pub fn validate(filepath: &str) -> Result<Header, RecipeError> {
+        let source = Source::open(filepath)?;
+        let open_error = |e| RecipeError::Open {
+            path: source.path().to_path_buf(),
+            source: e,
+        };
+        let file = source.reader().map_err(open_error)?;
+        let actual = file.metadata().map_err(open_error)?.len();
+        let mut reader = BufReader::new(file);
+        let header = Self::read_header(&mut reader, false)?;
+        CarneAsadeFile::read_variable_block(&mut reader, &header)?;
+        let expected = u64::from(header.sample_start()) + u64::from(header.sample_bytes()?);
+        if actual != expected {
+            return Err(RecipeError::SizeMismatch { expected, actual });
+        }
+        Ok(header)
+    }
+
     #[must_use]
     pub fn calculate_chunks(
         total_bytes: u32,
@@ -139,6 +522,27 @@ impl CarneAsada {
         max_threads: u32,
         step_count: u32,
         sample_size: u32,
+    ) -> Vec<Vec<(u32, u32)>> {
+        Self::calculate_chunks_between(
+            0,
+            total_bytes,
+            max_bytes,
+            max_threads,
+            step_count,
+            sample_size,
+        )
+    }
+
+    /// Like [`CarneAsada::calculate_chunks`], covering only the sample bytes
+    /// from `first_byte` up to `last_byte`.
+    #[must_use]
+    pub fn calculate_chunks_between(
+        first_byte: u32,
+        last_byte: u32,
+        max_bytes: u32,
+        max_threads: u32,
+        step_count: u32,
+        sample_size: u32,
     ) -> Vec<Vec<(u32, u32)>> {
         let mut chunks = Vec::<Vec<(u32, u32)>>::new();
 
@@ -150,20 +554,24 @@ impl CarneAsada {
             return chunks;
         }
 
+        let total_bytes = last_byte.saturating_sub(first_byte);
         let adjusted_max_bytes: u32 =
             (max_bytes / (step_count * sample_size)) * (step_count * sample_size);
+        if adjusted_max_bytes < 1 {
+            return chunks;
+        }
         let mut total_iterations: u32 = total_bytes / (adjusted_max_bytes * max_threads);
-        if total_bytes % (adjusted_max_bytes * max_threads) > 0 {
+        if !total_bytes.is_multiple_of(adjusted_max_bytes * max_threads) {
             total_iterations += 1;
         }
 
-        let mut counter = 0_u32;
+        let mut counter = first_byte;
         for _ in 0..total_iterations {
             let mut thread_chunks = Vec::<(u32, u32)>::new();
             for _ in 0..max_threads {
-                if counter + adjusted_max_bytes > total_bytes {
-                    thread_chunks.push((counter, total_bytes));
-                    counter += total_bytes - counter;
+                if adjusted_max_bytes > last_byte - counter {
+                    thread_chunks.push((counter, last_byte));
+                    counter = last_byte;
                     break;
                 }
 
@@ -177,90 +585,147 @@ impl CarneAsada {
 }
 
 impl Recipe for CarneAsada {
-    fn parse(&self, command: &Command<ParseRecipe>) -> Option<Event<RecipeParsed>> {
-        let dir = std::sync::Arc::new(
-            std::path::Path::new(&command.payload.basepath)
-                .join(command.payload.identifier.to_string()),
-        );
-        fs::create_dir(dir.as_ref()).expect("Could not create directory.");
+    /// Certain when the magic number and header checksum match, possible
+    /// when only the magic number does, as for files parsed leniently.
+    fn probe(&self, head: &[u8]) -> Confidence {
+        if !head.starts_with(CARNE_ASADA_MAGIC_NUMBER.as_bytes()) {
+            return Confidence::None;
+        }
+        let Some(header) = head.get(10..HEADER_END as usize) else {
+            return Confidence::Possible;
+        };
+        let mut buffer = [0u8; 512];
+        buffer.copy_from_slice(header);
+        match Checksum::of(u16::from_le_bytes([head[8], head[9]]), &buffer) {
+            Checksum::Valid => Confidence::Certain,
+            Checksum::Mismatch | Checksum::Absent => Confidence::Possible,
+        }
+    }
 
-        let file: File = File::open(&command.payload.filepath).expect("Could not open file.");
+    fn parse(
+        &self,
+        command: &Command<ParseRecipe>,
+        pipeline: &mut Pipeline,
+    ) -> Result<Event<RecipeParsed>, RecipeError> {
+        let source = std::sync::Arc::new(Source::open(&command.payload.filepath)?);
+        let file: File = source.reader().map_err(|e| RecipeError::Open {
+            path: source.path().to_path_buf(),
+            source: e,
+        })?;
         let mut reader: BufReader<File> = BufReader::new(file);
-        let mut buffer: [u8; 10] = [0u8; 10];
-        reader
-            .read_exact(&mut buffer)
-            .expect("Could not read magic header buffer");
-        if std::str::from_utf8(&buffer[..8]).expect("Invalid magic number.")
-            != CARNE_ASADA_MAGIC_NUMBER
-        {
-            return None;
-        }
-        let mut header_buffer: [u8; 512] = [0u8; 512];
-        reader
-            .read_exact(&mut header_buffer)
-            .expect("Could not read header buffer");
-        let header_data: Header = Header::parse(&header_buffer);
-        let metadata = metadata::Metadata {
-            size: header_data.size,
-            date_of_recipe: header_data.date_of_recipe,
-            time_of_recipe: header_data.time_of_recipe,
-            number_of_steps: header_data.number_of_steps,
-            steps: header_data
-                .steps
-                .to_vec()
+        let header_data: Header = Self::read_header(&mut reader, command.payload.options.lenient)?;
+        let variable_block = CarneAsadeFile::read_variable_block(&mut reader, &header_data)?;
+        let dir = std::sync::Arc::new(command.payload.output_dir());
+        let window =
+            header_data.window(command.payload.options.start, command.payload.options.end)?;
+        // Refused before any output is written, rather than read in part.
+        header_data.sample_bytes()?;
+        let first = header_data.timestamp_of(window.start);
+        let selected = header_data.select_steps(&command.payload.options.steps)?;
+        let mut metadata = metadata::Metadata {
+            size: u32::try_from(window.end - window.start).unwrap_or(u32::MAX),
+            date_of_recipe: first.date(),
+            time_of_recipe: first.time(),
+            first_sample: window.start,
+            number_of_steps: u16::try_from(selected.len()).unwrap_or(u16::MAX),
+            steps: selected
                 .iter()
-                .filter_map(|i| {
-                    if i > &0 {
-                        Some(String::from(STEPS_BY_NAME[*i]))
-                    } else {
-                        None
-                    }
-                })
+                .map(|i| header_data.step_name(*i).to_string())
                 .collect(),
-            units: 1.0,
-            granularity: header_data.granularity,
+            step_quality: selected
+                .iter()
+                .map(|i| header_data.step_quality[*i])
+                .collect(),
+            unit_conversion: selected
+                .iter()
+                .map(|i| header_data.unit_conversion[*i])
+                .collect(),
+            variable_block_text: String::from_utf8(variable_block.clone()).ok(),
+            variable_block,
+            ..metadata::Metadata::from(&header_data)
         };
-        metadata.store(dir.join(METADATA_FILENAME));
+        pipeline.begin(&mut metadata)?;
+        let metadata_path = dir.join(METADATA_FILENAME);
+        metadata
+            .store(metadata_path.clone())
+            .map_err(output_error(&metadata_path))?;
 
         let step_count = u32::from(header_data.number_of_steps);
-        let total_bytes = header_data.size * step_count * 2;
-        let chunks = Self::calculate_chunks(total_bytes, MAX_BYTES, MAX_THREADS, step_count, DTYPE);
+        let frame_bytes = u64::from(step_count * DTYPE);
+        let byte_of = |sample: u64| {
+            let bytes = sample * frame_bytes;
+            u32::try_from(bytes).map_err(|_| RecipeError::SamplesTooLarge { bytes })
+        };
+        let chunks = Self::calculate_chunks_between(
+            byte_of(window.start)?,
+            byte_of(window.end)?,
+            MAX_BYTES,
+            MAX_THREADS,
+            step_count,
+            DTYPE,
+        );
+        let sample_start = header_data.sample_start();
 
-        let mut generated_files = Vec::<(usize, u32, String)>::new();
+        let mut encoder = command
+            .payload
+            .options
+            .format
+            .encoder(&dir, command.payload.options.keep_intermediates);
+        encoder.begin(&metadata)?;
+        let raw = encoder.accepts_raw() && pipeline.is_empty();
+        let every_step = selected
+            .iter()
+            .copied()
+            .eq(0..usize::from(header_data.number_of_steps));
+        let selected = std::sync::Arc::new(selected);
         for chunk in chunks {
             let mut threads = Vec::new();
             for thread in chunk {
-                let dir = dir.clone();
-                let filepath = command.payload.filepath.clone();
-                threads.push(thread::spawn(move || {
-                    CarneAsadaGaucamole::parse_guacamole(
-                        &dir,
-                        &filepath,
-                        thread.0,
-                        thread.1,
-                        522,
-                        header_data.number_of_steps,
-                        &header_data.unit_conversion,
-                    )
-                }));
+                let source = source.clone();
+                let selected = selected.clone();
+                threads.push((
+                    thread,
+                    thread::spawn(move || {
+                        let buffer =
+                            CarneAsadeFile::read_chunk(&source, thread.0, thread.1, sample_start)?;
+                        if raw && every_step {
+                            return Ok(Guacamole::Raw(buffer));
+                        }
+                        if raw {
+                            return Ok(Guacamole::Raw(CarneAsadaGaucamole::select_raw(
+                                &buffer,
+                                header_data.number_of_steps,
+                                &selected,
+                            )));
+                        }
+                        Ok(Guacamole::Decoded(CarneAsadaGaucamole::decode_steps(
+                            &buffer,
+                            header_data.number_of_steps,
+                            &header_data.unit_conversion,
+                            &selected,
+                        )))
+                    }),
+                ));
             }
-            for t in threads {
-                generated_files.append(&mut t.join().expect("Could not join thread."));
+            for ((onset, offset), t) in threads {
+                let guac = t.join().map_err(|_| RecipeError::ChunkRead {
+                    onset,
+                    offset,
+                    source: io::Error::other("Decoding thread panicked."),
+                })??;
+                let onset = u64::from(onset) / frame_bytes - window.start;
+                match guac {
+                    Guacamole::Raw(buffer) => encoder.encode_raw(onset, &buffer)?,
+                    Guacamole::Decoded(guac) => pipeline.encode(guac, encoder.as_mut())?,
+                }
             }
         }
+        pipeline.finish(encoder.as_mut())?;
+        let output = encoder.finish()?;
 
-        generated_files.sort_by(|a, b| match a.0.cmp(&b.0) {
-            Ordering::Equal => a.1.cmp(&b.1),
-            other => other,
-        });
-
-        CarneAsadeFile::merge(&dir, &generated_files);
-
-        Some(Event {
+        Ok(Event {
             event_type: 0,
-            payload: RecipeParsed {
-                output: dir.join(FILENAME),
-            },
+            payload: RecipeParsed { output },
         })
     }
 
@@ -272,124 +737,806 @@ impl Recipe for CarneAsada {
 pub struct CarneAsadeFile {}
 
 impl CarneAsadeFile {
-    #[must_use]
-    pub fn read_chunk(filepath: &String, onset: u32, offset: u32, start: u32) -> Vec<u8> {
-        let file: File = File::open(filepath).expect("Could not open file.");
-        let mut reader: BufReader<File> = BufReader::new(file);
-        let mut buffer = vec![
-            0;
-            usize::try_from(offset - onset)
-                .expect("Could not create buffer from onset-offset.")
-        ];
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::VariableBlockRead`] when the block declared by
+    /// the header cannot be read in full.
+    pub fn read_variable_block<R: Read + Seek>(
+        reader: &mut R,
+        header: &Header,
+    ) -> Result<Vec<u8>, RecipeError> {
+        let variable_block_error = |source| RecipeError::VariableBlockRead { source };
         reader
-            .seek(SeekFrom::Start((start + onset).into()))
-            .expect("Could not seek.");
-        reader.read_exact(&mut buffer).expect("Could not read.");
-        buffer
+            .seek(SeekFrom::Start(header.variable_block_start().into()))
+            .map_err(variable_block_error)?;
+        let mut block = Vec::new();
+        reader
+            .take(header.variable_block_size.into())
+            .read_to_end(&mut block)
+            .map_err(variable_block_error)?;
+        if block.len() < header.variable_block_size as usize {
+            return Err(variable_block_error(io::Error::from(
+                io::ErrorKind::UnexpectedEof,
+            )));
+        }
+        Ok(block)
     }
 
-    #[must_use]
-    pub fn write_chunk(
-        dir: &Path,
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::ChunkRead`] when the chunk cannot be read.
+    pub fn read_chunk(
+        source: &Source,
         onset: u32,
         offset: u32,
-        step: usize,
-        guac: &Vec<f64>,
-    ) -> (usize, u32, String) {
-        let filename = format!("{onset}_{offset}_{step}.json");
-        let file = File::create(dir.join(&filename)).expect("Could not create file.");
-        let writer = BufWriter::new(file);
-        serde_json::to_writer(writer, &guac).expect("Could not write json.");
-        (step, onset, filename)
-    }
-
-    pub fn merge(dir: &Path, files: &[(usize, u32, String)]) {
-        let mut custom = String::from(GUACAMOLE_START_SLICE).as_bytes().to_vec();
-        let mut header = fs::read(dir.join(METADATA_FILENAME)).expect("Could not reader metadata.");
-        header.remove(header.len() - 1);
-        header.append(&mut custom);
-        std::fs::write(dir.join(FILENAME), &header).expect("Could not copy header.");
-        let mut recipe_file = std::fs::OpenOptions::new()
-            .append(true)
-            .open(dir.join(FILENAME))
-            .expect("Could not open in append mode.");
-        let mut current_step: usize = usize::MAX;
-        for f in files {
-            if current_step == usize::MAX {
-                current_step = f.0;
-            }
-
-            if f.0 != current_step {
-                recipe_file
-                    .write_all(String::from("],[").as_bytes())
-                    .expect("Could not write new step.");
-                current_step = f.0;
-            }
-
-            if f.1 > 0 {
-                recipe_file
-                    .write_all(DELIMITER.as_bytes())
-                    .expect("Could not write delimiter.");
-            }
-
-            let r = fs::read(dir.join(&f.2)).expect("Could not read guac.");
-            recipe_file
-                .write_all(&r[1..r.len() - 1])
-                .expect("Could not write guac.");
-        }
-        recipe_file
-            .write_all(String::from(GUACAMOLE_END_SLICE).as_bytes())
-            .expect("Could not write recipe end slice.");
+        start: u32,
+    ) -> Result<Vec<u8>, RecipeError> {
+        let chunk_error = |source| RecipeError::ChunkRead {
+            onset,
+            offset,
+            source,
+        };
+        let file: File = source.reader().map_err(chunk_error)?;
+        let mut reader: BufReader<File> = BufReader::new(file);
+        let mut buffer = vec![0; (offset - onset) as usize];
+        reader
+            .seek(SeekFrom::Start(u64::from(start) + u64::from(onset)))
+            .map_err(chunk_error)?;
+        reader.read_exact(&mut buffer).map_err(chunk_error)?;
+        Ok(buffer)
     }
 }
 
 pub struct CarneAsadaGaucamole {}
 
 impl CarneAsadaGaucamole {
-    #[must_use]
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::ChunkRead`] when the chunk cannot be read.
     pub fn parse_guacamole(
-        dir: &std::path::Path,
-        filepath: &String,
+        source: &Source,
         onset: u32,
         offset: u32,
         start: u32,
         number_of_steps: u16,
         unit_conversion: &[i32; 12],
-    ) -> Vec<(usize, u32, String)> {
-        let buffer = CarneAsadeFile::read_chunk(filepath, onset, offset, start);
-        let mut guac = vec![
-            vec![
-                0.0;
-                ((offset - onset) / u32::from(number_of_steps) / DTYPE)
-                    .try_into()
-                    .expect("")
-            ];
-            usize::from(number_of_steps)
-        ];
-        for (idx, sample) in buffer.chunks(2).enumerate() {
-            let step: usize = idx % usize::from(number_of_steps);
-            let val = u16::from_le_bytes(sample[0..2].try_into().expect(""));
-            let step_idx: usize = idx / usize::from(number_of_steps);
-            if val > 32767_u16 {
-                guac[step][step_idx] = (f64::from(val) - 65536_f64)
-                    * f64::from(unit_conversion[step])
-                    * 10_f64.powi(-6);
-            } else {
-                guac[step][step_idx] =
-                    f64::from(val) * f64::from(unit_conversion[step]) * 10_f64.powi(-6);
+    ) -> Result<Vec<Vec<f64>>, RecipeError> {
+        let buffer = CarneAsadeFile::read_chunk(source, onset, offset, start)?;
+        Ok(Self::decode(&buffer, number_of_steps, unit_conversion))
+    }
+
+    /// Splits interleaved little-endian samples into one vector per step,
+    /// scaled by each step's unit conversion (nV) into mV.
+    #[must_use]
+    pub fn decode(
+        buffer: &[u8],
+        number_of_steps: u16,
+        unit_conversion: &[i32; 12],
+    ) -> Vec<Vec<f64>> {
+        let steps: Vec<usize> = (0..usize::from(number_of_steps)).collect();
+        Self::decode_steps(buffer, number_of_steps, unit_conversion, &steps)
+    }
+
+    /// Like [`CarneAsadaGaucamole::decode`], but only for the steps at
+    /// `steps`, in that order; the other samples are never converted.
+    #[must_use]
+    pub fn decode_steps(
+        buffer: &[u8],
+        number_of_steps: u16,
+        unit_conversion: &[i32; 12],
+        steps: &[usize],
+    ) -> Vec<Vec<f64>> {
+        if number_of_steps == 0 {
+            return Vec::new();
+        }
+        let frame = usize::from(number_of_steps) * DTYPE as usize;
+        steps
+            .iter()
+            .map(|step| {
+                let at = step * DTYPE as usize;
+                buffer
+                    .chunks_exact(frame)
+                    .map(|frame| {
+                        f64::from(i16::from_le_bytes([frame[at], frame[at + 1]]))
+                            * f64::from(unit_conversion[*step])
+                            * 10_f64.powi(-6)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Interleaved samples of the steps at `steps` only, still raw.
+    #[must_use]
+    pub fn select_raw(buffer: &[u8], number_of_steps: u16, steps: &[usize]) -> Vec<u8> {
+        let frame = usize::from(number_of_steps) * DTYPE as usize;
+        if frame == 0 {
+            return Vec::new();
+        }
+        let mut selected = Vec::with_capacity(buffer.len() / frame * steps.len() * DTYPE as usize);
+        for frame in buffer.chunks_exact(frame) {
+            for step in steps {
+                let at = step * DTYPE as usize;
+                selected.extend_from_slice(&frame[at..at + DTYPE as usize]);
             }
         }
-        let mut generated_files = Vec::<(usize, u32, String)>::new();
-        for (i, g) in guac.iter().enumerate() {
-            generated_files.push(CarneAsadeFile::write_chunk(dir, onset, offset, i, g));
+        selected
+    }
+}
+
+/// Builders for small recordings used by tests across the crate.
+#[cfg(test)]
+pub(crate) mod fixture {
+    use std::path::Path;
+
+    use super::{CarneAsada, Header, CARNE_ASADA_MAGIC_NUMBER};
+    use crate::command::{Command, CommandHandler};
+    use crate::event::Event;
+    use crate::recipe::{ParseRecipe, ParseRecipeCommandHandler, RecipeError, RecipeParsed};
+
+    /// Parses `filepath` into a new directory under `basepath`, with every
+    /// other option left at its default.
+    pub fn command(basepath: &Path, filepath: &Path) -> Command<ParseRecipe> {
+        Command {
+            command_type: 0,
+            payload: ParseRecipe {
+                basepath: basepath.to_str().unwrap().into(),
+                filepath: filepath.to_str().unwrap().into(),
+                identifier: uuid::Uuid::new_v4(),
+                ..ParseRecipe::default()
+            },
         }
-        generated_files
+    }
+
+    /// The command handler, which owns the output directory, with only this
+    /// recipe registered.
+    pub fn handler() -> ParseRecipeCommandHandler {
+        let mut handler = ParseRecipeCommandHandler::default();
+        handler.register(Box::new(CarneAsada {}));
+        handler
+    }
+
+    /// Parses through [`handler`].
+    pub fn parse(command: &Command<ParseRecipe>) -> Result<Event<RecipeParsed>, RecipeError> {
+        handler().handle(command)
+    }
+
+    /// A recording starting 2008-11-05 05:09:00 with one `(code, resolution)`
+    /// pair per step and `samples[step][i]` raw values.
+    pub fn recording(granularity: u16, steps: &[(u16, i16)], samples: &[Vec<i16>]) -> Vec<u8> {
+        let size = samples.first().map_or(0, Vec::len);
+        let mut header = [0u8; 512];
+        header[4..8].copy_from_slice(&u32::try_from(size).unwrap().to_le_bytes());
+        header[8..12].copy_from_slice(&522_u32.to_le_bytes());
+        header[12..16].copy_from_slice(&522_u32.to_le_bytes());
+        header[128] = 5;
+        header[130] = 11;
+        header[132..134].copy_from_slice(&2008_u16.to_le_bytes());
+        header[140] = 5;
+        header[142] = 9;
+        header[146..148].copy_from_slice(&u16::try_from(steps.len()).unwrap().to_le_bytes());
+        for (i, (code, resolution)) in steps.iter().enumerate() {
+            header[148 + i * 2..150 + i * 2].copy_from_slice(&code.to_le_bytes());
+            header[196 + i * 2..198 + i * 2].copy_from_slice(&resolution.to_le_bytes());
+        }
+        header[262..264].copy_from_slice(&granularity.to_le_bytes());
+        let mut bytes = CARNE_ASADA_MAGIC_NUMBER.as_bytes().to_vec();
+        bytes.extend_from_slice(&Header::checksum(&header).to_le_bytes());
+        bytes.extend_from_slice(&header);
+        for i in 0..size {
+            for step in samples {
+                bytes.extend_from_slice(&step[i].to_le_bytes());
+            }
+        }
+        bytes
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+
+    use tempfile::{tempdir, NamedTempFile};
+
+    use super::fixture::command;
     use super::*;
+    use crate::encoder::json::FILENAME;
+
+    fn header_buffer() -> [u8; 512] {
+        let mut buffer = [0u8; 512];
+        buffer[128] = 5;
+        buffer[130] = 11;
+        buffer[132..134].copy_from_slice(&2008_u16.to_le_bytes());
+        buffer[140] = 5;
+        buffer[142] = 9;
+        buffer[146] = 1;
+        buffer
+    }
+
+    fn recipe_file(checksum: u16, header: &[u8; 512]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(CARNE_ASADA_MAGIC_NUMBER.as_bytes()).unwrap();
+        file.write_all(&checksum.to_le_bytes()).unwrap();
+        file.write_all(header).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_given_compressed_recording_when_parse_then_same_recipe_as_plain() {
+        let generator = generator::Generator {
+            duration: std::time::Duration::from_secs(300),
+            ..generator::Generator::default()
+        };
+        let plain = NamedTempFile::new().unwrap();
+        generator.write_to(plain.path()).unwrap();
+        let bytes = fs::read(plain.path()).unwrap();
+
+        let gz = NamedTempFile::new().unwrap();
+        let mut encoder =
+            flate2::write::GzEncoder::new(gz.reopen().unwrap(), flate2::Compression::fast());
+        encoder.write_all(&bytes).unwrap();
+        encoder.finish().unwrap();
+        let zip = NamedTempFile::new().unwrap();
+        let mut writer = zip::ZipWriter::new(zip.reopen().unwrap());
+        for name in ["first.dat", "second.dat"] {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(&bytes).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let dir = tempdir().unwrap();
+        let recipe = |filepath: &Path| {
+            let output = fixture::parse(&command(dir.path(), filepath))
+                .unwrap()
+                .payload
+                .output;
+            fs::read(output).unwrap()
+        };
+        let expected = recipe(plain.path());
+        assert_eq!(recipe(gz.path()), expected);
+        assert_eq!(
+            recipe(Path::new(&format!("{}#second.dat", zip.path().display()))),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_given_heads_when_probe_then_confidence_follows_magic_and_checksum() {
+        let header = header_buffer();
+        let head = |checksum: u16| {
+            let mut head = CARNE_ASADA_MAGIC_NUMBER.as_bytes().to_vec();
+            head.extend_from_slice(&checksum.to_le_bytes());
+            head.extend_from_slice(&header);
+            head
+        };
+        let good = head(Header::checksum(&header));
+        assert_eq!(CarneAsada {}.probe(&good), Confidence::Certain);
+        assert_eq!(CarneAsada {}.probe(&head(0)), Confidence::Possible);
+        assert_eq!(CarneAsada {}.probe(&good[..100]), Confidence::Possible);
+        assert_eq!(CarneAsada {}.probe(b"ISHNE1.0"), Confidence::None);
+    }
+
+    #[test]
+    fn test_given_truncated_samples_when_handled_then_partial_output_removed() {
+        let dir = tempdir().unwrap();
+        let mut recording = fixture::recording(200, &[(5, 1000)], &[vec![1; 64]]);
+        recording.truncate(recording.len() - 10);
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&recording).unwrap();
+        let cmd = command(dir.path(), file.path());
+        assert!(matches!(
+            fixture::parse(&cmd),
+            Err(RecipeError::ChunkRead { .. })
+        ));
+        assert!(!cmd.payload.output_dir().exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_given_recordings_when_validate_then_size_and_checksum_checked() {
+        let recording = fixture::recording(200, &[(5, 1000), (6, 1000)], &[vec![1; 8], vec![2; 8]]);
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&recording).unwrap();
+        let filepath = file.path().to_str().unwrap().to_string();
+        assert_eq!(CarneAsada::validate(&filepath).unwrap().size, 8);
+
+        file.write_all(&[0; 3]).unwrap();
+        assert!(matches!(
+            CarneAsada::validate(&filepath),
+            Err(RecipeError::SizeMismatch {
+                expected: 554,
+                actual: 557
+            })
+        ));
+
+        let header = header_buffer();
+        let file = recipe_file(Header::checksum(&header) ^ 0xFFFF, &header);
+        assert!(matches!(
+            CarneAsada::validate(file.path().to_str().unwrap()),
+            Err(RecipeError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_given_step_counts_when_parse_header_then_out_of_range_rejected() {
+        for steps in [0_u16, 13, u16::MAX] {
+            let mut header = header_buffer();
+            header[146..148].copy_from_slice(&steps.to_le_bytes());
+            assert!(matches!(
+                Header::parse(&header),
+                Err(RecipeError::InvalidStepCount { steps: got, max: 12 }) if got == steps
+            ));
+        }
+        let mut header = header_buffer();
+        header[146] = 12;
+        assert_eq!(Header::parse(&header).unwrap().number_of_steps, 12);
+    }
+
+    #[test]
+    fn test_given_size_past_u32_bytes_when_parse_or_validate_then_samples_too_large() {
+        let dir = tempdir().unwrap();
+        let mut header = header_buffer();
+        header[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        header[146] = 2;
+        let file = recipe_file(Header::checksum(&header), &header);
+        let cmd = command(dir.path(), file.path());
+        assert!(matches!(
+            fixture::parse(&cmd),
+            Err(RecipeError::SamplesTooLarge {
+                bytes: 17_179_869_180
+            })
+        ));
+        assert!(!cmd.payload.output_dir().exists());
+        assert!(matches!(
+            CarneAsada::validate(file.path().to_str().unwrap()),
+            Err(RecipeError::SamplesTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_given_range_ending_at_u32_max_when_calculate_chunks_between_then_not_overflowed() {
+        let chunks = CarneAsada::calculate_chunks_between(u32::MAX - 1030, u32::MAX, 512, 2, 1, 2);
+        assert_eq!(
+            chunks,
+            vec![
+                vec![
+                    (u32::MAX - 1030, u32::MAX - 518),
+                    (u32::MAX - 518, u32::MAX - 6)
+                ],
+                vec![(u32::MAX - 6, u32::MAX)]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_given_frame_larger_than_max_bytes_when_calculate_chunks_then_none() {
+        assert!(CarneAsada::calculate_chunks(2000, 10, 8, 6, 2).is_empty());
+    }
+
+    #[test]
+    fn test_given_check_string_when_crc_then_ccitt_check_value() {
+        assert_eq!(crc_ccitt(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn test_given_bad_checksum_when_parse_then_checksum_mismatch() {
+        let dir = tempdir().unwrap();
+        let header = header_buffer();
+        let actual = Header::checksum(&header);
+        let file = recipe_file(actual ^ 0xFFFF, &header);
+        let result = CarneAsada {}.parse(
+            &command(dir.path(), file.path()),
+            &mut ParseRecipe::default().pipeline(),
+        );
+        match result {
+            Err(RecipeError::ChecksumMismatch {
+                expected,
+                actual: got,
+            }) => {
+                assert_eq!(expected, actual ^ 0xFFFF);
+                assert_eq!(got, actual);
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_given_bad_checksum_and_lenient_when_parse_then_parsed() {
+        let dir = tempdir().unwrap();
+        let header = header_buffer();
+        let file = recipe_file(Header::checksum(&header) ^ 0xFFFF, &header);
+        let mut cmd = command(dir.path(), file.path());
+        cmd.payload.options.lenient = true;
+        assert!(fixture::parse(&cmd).is_ok());
+    }
+
+    #[test]
+    fn test_given_no_checksum_when_parse_then_parsed_only_leniently() {
+        let dir = tempdir().unwrap();
+        let header = header_buffer();
+        assert_ne!(Header::checksum(&header), NO_CHECKSUM);
+        let file = recipe_file(NO_CHECKSUM, &header);
+        let mut cmd = command(dir.path(), file.path());
+        assert!(matches!(
+            fixture::parse(&cmd),
+            Err(RecipeError::ChecksumMismatch {
+                expected: NO_CHECKSUM,
+                ..
+            })
+        ));
+        assert!(CarneAsada::validate(file.path().to_str().unwrap()).is_err());
+        cmd.payload.options.lenient = true;
+        assert!(fixture::parse(&cmd).is_ok());
+    }
+
+    #[test]
+    fn test_given_good_checksum_when_parse_then_parsed() {
+        let dir = tempdir().unwrap();
+        let header = header_buffer();
+        let file = recipe_file(Header::checksum(&header), &header);
+        assert!(fixture::parse(&command(dir.path(), file.path())).is_ok());
+    }
+
+    #[test]
+    fn test_given_variable_block_when_parse_then_samples_start_at_declared_offset() {
+        let dir = tempdir().unwrap();
+        let mut header = header_buffer();
+        header[0..4].copy_from_slice(&4_u32.to_le_bytes());
+        header[4..8].copy_from_slice(&2_u32.to_le_bytes());
+        header[8..12].copy_from_slice(&522_u32.to_le_bytes());
+        header[12..16].copy_from_slice(&526_u32.to_le_bytes());
+        header[146] = 1;
+        header[148] = 5;
+        header[196..198].copy_from_slice(&1000_i16.to_le_bytes());
+        let mut file = recipe_file(Header::checksum(&header), &header);
+        file.write_all(b"hola").unwrap();
+        file.write_all(&100_i16.to_le_bytes()).unwrap();
+        file.write_all(&(-200_i16).to_le_bytes()).unwrap();
+        let cmd = command(dir.path(), file.path());
+        fixture::parse(&cmd).unwrap();
+        let recipe: serde_json::Value = serde_json::from_slice(
+            &fs::read(
+                dir.path()
+                    .join(cmd.payload.identifier.to_string())
+                    .join(FILENAME),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(recipe["variable_block_text"], "hola");
+        assert_eq!(recipe["variable_block"], serde_json::json!(b"hola"));
+        assert_eq!(recipe["guacamole"], serde_json::json!([[0.1, -0.2]]));
+    }
+
+    #[test]
+    fn test_given_truncated_variable_block_when_parse_then_variable_block_read() {
+        let dir = tempdir().unwrap();
+        let mut header = header_buffer();
+        header[0..4].copy_from_slice(&64_u32.to_le_bytes());
+        let mut file = recipe_file(Header::checksum(&header), &header);
+        file.write_all(b"hola").unwrap();
+        assert!(matches!(
+            CarneAsada {}.parse(
+                &command(dir.path(), file.path()),
+                &mut ParseRecipe::default().pipeline(),
+            ),
+            Err(RecipeError::VariableBlockRead { .. })
+        ));
+    }
+
+    fn single_step_file(samples: &[i16]) -> NamedTempFile {
+        let mut header = header_buffer();
+        header[4..8].copy_from_slice(&u32::try_from(samples.len()).unwrap().to_le_bytes());
+        header[146] = 1;
+        header[148] = 5;
+        header[196..198].copy_from_slice(&1000_i16.to_le_bytes());
+        let mut file = recipe_file(Header::checksum(&header), &header);
+        for sample in samples {
+            file.write_all(&sample.to_le_bytes()).unwrap();
+        }
+        file
+    }
+
+    fn output_files(dir: &Path) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_given_parsed_recipe_then_intermediate_chunks_removed() {
+        let dir = tempdir().unwrap();
+        let file = single_step_file(&[1, 2, 3]);
+        let cmd = command(dir.path(), file.path());
+        fixture::parse(&cmd).unwrap();
+        assert_eq!(
+            output_files(&dir.path().join(cmd.payload.identifier.to_string())),
+            vec![METADATA_FILENAME, FILENAME]
+        );
+    }
+
+    #[test]
+    fn test_given_keep_intermediates_when_parse_then_chunks_kept() {
+        let dir = tempdir().unwrap();
+        let file = single_step_file(&[1, 2, 3]);
+        let mut cmd = command(dir.path(), file.path());
+        cmd.payload.options.keep_intermediates = true;
+        fixture::parse(&cmd).unwrap();
+        assert_eq!(
+            output_files(&dir.path().join(cmd.payload.identifier.to_string())),
+            vec!["0_3_0.json", METADATA_FILENAME, FILENAME]
+        );
+    }
+
+    #[test]
+    fn test_given_window_when_parse_then_only_its_samples_decoded() {
+        let dir = tempdir().unwrap();
+        let samples: Vec<i16> = (0..10).collect();
+        let recording = fixture::recording(4, &[(5, 1000), (6, 1000)], &[samples.clone(), samples]);
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&recording).unwrap();
+        let mut cmd = command(dir.path(), file.path());
+        cmd.payload.options.start = Some(WindowBound::Sample(3));
+        cmd.payload.options.end = Some("2008-11-05T05:09:02".parse().unwrap());
+        let output = fixture::parse(&cmd).unwrap().payload.output;
+
+        let recipe: serde_json::Value =
+            serde_json::from_slice(&fs::read(&output).unwrap()).unwrap();
+        let step: Vec<f64> = serde_json::from_value(recipe["guacamole"][1].clone()).unwrap();
+        assert_eq!(step.len(), 5);
+        assert!(step
+            .iter()
+            .zip([0.003, 0.004, 0.005, 0.006, 0.007])
+            .all(|(a, b)| (a - b).abs() < 1e-9));
+        let metadata: metadata::Metadata =
+            serde_json::from_slice(&fs::read(output.with_file_name(METADATA_FILENAME)).unwrap())
+                .unwrap();
+        assert_eq!(metadata.size, 5);
+        assert_eq!(metadata.first_sample, 3);
+        assert_eq!(metadata.time_of_recipe.to_string(), "05:09:00.750");
+    }
+
+    #[test]
+    fn test_given_step_selection_when_parse_then_only_those_steps_written() {
+        let dir = tempdir().unwrap();
+        let recording = fixture::recording(
+            4,
+            &[(5, 1000), (6, 2000), (11, 3000)],
+            &[vec![1, 2], vec![3, 4], vec![5, 6]],
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&recording).unwrap();
+        let mut cmd = command(dir.path(), file.path());
+        cmd.payload.options.steps = vec![
+            StepSelector::Name(String::from("v1")),
+            StepSelector::Index(0),
+            StepSelector::Name(String::from("I")),
+        ];
+        let output = fixture::parse(&cmd).unwrap().payload.output;
+
+        let recipe: serde_json::Value =
+            serde_json::from_slice(&fs::read(&output).unwrap()).unwrap();
+        let guacamole: Vec<Vec<f64>> = serde_json::from_value(recipe["guacamole"].clone()).unwrap();
+        assert_eq!(guacamole.len(), 2);
+        assert!((guacamole[0][1] - 0.018).abs() < 1e-9);
+        assert!((guacamole[1][1] - 0.002).abs() < 1e-9);
+        let metadata: metadata::Metadata =
+            serde_json::from_slice(&fs::read(output.with_file_name(METADATA_FILENAME)).unwrap())
+                .unwrap();
+        assert_eq!(metadata.number_of_steps, 2);
+        assert_eq!(metadata.steps, ["V1", "I"]);
+        assert_eq!(metadata.unit_conversion, [3000, 1000]);
+    }
+
+    #[test]
+    fn test_given_derive_leads_when_parse_then_limb_leads_appended() {
+        let dir = tempdir().unwrap();
+        let recording = fixture::recording(4, &[(5, 1000), (6, 1000)], &[vec![2, 4], vec![6, 8]]);
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&recording).unwrap();
+        let mut cmd = command(dir.path(), file.path());
+        cmd.payload.options.derive_leads = true;
+        cmd.payload.options.format = crate::encoder::Format::Csv;
+        let output = fixture::parse(&cmd).unwrap().payload.output;
+
+        let csv = fs::read_to_string(&output).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("time,I,II,III,VR,VL,VF"));
+        let row: Vec<f64> = lines
+            .next()
+            .unwrap()
+            .split(',')
+            .map(|v| v.parse().unwrap())
+            .collect();
+        let expected = [0.0, 0.002, 0.006, 0.004, -0.004, -0.001, 0.005];
+        assert!(row.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-9));
+        let metadata: metadata::Metadata =
+            serde_json::from_slice(&fs::read(output.with_file_name(METADATA_FILENAME)).unwrap())
+                .unwrap();
+        assert_eq!(metadata.number_of_steps, 6);
+        assert_eq!(metadata.derived_steps, ["III", "VR", "VL", "VF"]);
+    }
+
+    #[test]
+    fn test_given_missing_step_when_parse_then_unknown_step() {
+        let dir = tempdir().unwrap();
+        let file = single_step_file(&[1, 2, 3]);
+        let mut cmd = command(dir.path(), file.path());
+        cmd.payload.options.steps = vec![StepSelector::Name(String::from("V6"))];
+        let err = fixture::parse(&cmd).unwrap_err();
+        assert!(matches!(err, RecipeError::UnknownStep { .. }));
+        assert_eq!(err.to_string(), "recording has no step V6 (steps are I)");
+        cmd.payload.options.steps = vec![StepSelector::Index(1)];
+        assert!(matches!(
+            fixture::parse(&cmd),
+            Err(RecipeError::UnknownStep { .. })
+        ));
+    }
+
+    #[test]
+    fn test_given_selected_steps_when_select_raw_then_frames_narrowed() {
+        let buffer = [1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6, 0];
+        assert_eq!(
+            CarneAsadaGaucamole::select_raw(&buffer, 3, &[2, 0]),
+            [3, 0, 1, 0, 6, 0, 4, 0]
+        );
+    }
+
+    #[test]
+    fn test_given_empty_window_when_parse_then_invalid_window() {
+        let dir = tempdir().unwrap();
+        let file = single_step_file(&[1, 2, 3]);
+        let mut cmd = command(dir.path(), file.path());
+        cmd.payload.options.start = Some(WindowBound::Sample(5));
+        assert!(matches!(
+            fixture::parse(&cmd),
+            Err(RecipeError::InvalidWindow {
+                start: 3,
+                end: 3,
+                size: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_given_byte_range_when_calculate_chunks_between_then_only_range_covered() {
+        let chunks = CarneAsada::calculate_chunks_between(600, 2000, 512, 2, 3, 2);
+        assert_eq!(
+            chunks,
+            vec![vec![(600, 1110), (1110, 1620)], vec![(1620, 2000)]]
+        );
+    }
+
+    #[test]
+    fn test_given_bad_magic_when_parse_then_bad_magic_number() {
+        let dir = tempdir().unwrap();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"TORTILLA10").unwrap();
+        let cmd = command(dir.path(), file.path());
+        assert!(matches!(
+            CarneAsada {}.parse(&cmd, &mut cmd.payload.pipeline()),
+            Err(RecipeError::BadMagicNumber)
+        ));
+        assert!(!dir.path().join(cmd.payload.identifier.to_string()).exists());
+    }
+
+    #[test]
+    fn test_given_truncated_header_when_parse_then_short_header() {
+        let dir = tempdir().unwrap();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"CARNE1.0\0\0").unwrap();
+        file.write_all(&[0u8; 100]).unwrap();
+        assert!(matches!(
+            CarneAsada {}.parse(
+                &command(dir.path(), file.path()),
+                &mut ParseRecipe::default().pipeline(),
+            ),
+            Err(RecipeError::ShortHeader)
+        ));
+    }
+
+    #[test]
+    fn test_given_invalid_date_when_parse_header_then_invalid_date() {
+        let mut buffer = [0u8; 512];
+        buffer[128] = 31;
+        buffer[130] = 2;
+        buffer[132..134].copy_from_slice(&2008_u16.to_le_bytes());
+        assert!(matches!(
+            Header::parse(&buffer),
+            Err(RecipeError::InvalidDate {
+                year: 2008,
+                month: 2,
+                day: 31
+            })
+        ));
+    }
+
+    #[test]
+    fn test_given_full_header_when_parse_then_all_fields_decoded() {
+        let mut buffer = header_buffer();
+        buffer[0..4].copy_from_slice(&6_u32.to_le_bytes());
+        buffer[4..8].copy_from_slice(&300_000_u32.to_le_bytes());
+        buffer[8..12].copy_from_slice(&522_u32.to_le_bytes());
+        buffer[12..16].copy_from_slice(&528_u32.to_le_bytes());
+        buffer[16] = 1;
+        buffer[18..23].copy_from_slice(b"Jorge");
+        buffer[58..63].copy_from_slice(b"Pinto");
+        buffer[98..105].copy_from_slice(b"1-300mg");
+        buffer[118] = 2;
+        buffer[120] = 1;
+        buffer[122] = 14;
+        buffer[124] = 7;
+        buffer[126..128].copy_from_slice(&1961_u16.to_le_bytes());
+        buffer[134..140].copy_from_slice(&[0xF7, 0xFF, 0xF7, 0xFF, 0xF7, 0xFF]);
+        buffer[146] = 2;
+        buffer[148] = 5;
+        buffer[150] = 6;
+        buffer[172..174].copy_from_slice(&(-9_i16).to_le_bytes());
+        buffer[196..198].copy_from_slice(&4878_i16.to_le_bytes());
+        buffer[220] = 4;
+        buffer[222..229].copy_from_slice(b"digital");
+        buffer[262] = 200;
+        buffer[264..268].copy_from_slice(b"acme");
+        let header = Header::parse(&buffer).unwrap();
+        assert_eq!(header.variable_block_size, 6);
+        assert_eq!(header.size, 300_000);
+        assert_eq!(header.variable_block_offset, 522);
+        assert_eq!(header.sample_offset, 528);
+        assert_eq!(header.file_version, 1);
+        assert_eq!(header.patient.first_name, "Jorge");
+        assert_eq!(header.patient.last_name, "Pinto");
+        assert_eq!(header.patient.id, "1-300mg");
+        assert_eq!(header.patient.sex, metadata::Sex::Female);
+        assert_eq!(header.patient.race, metadata::Race::Caucasian);
+        assert_eq!(
+            header.patient.date_of_birth,
+            chrono::NaiveDate::from_ymd_opt(1961, 7, 14)
+        );
+        assert_eq!(header.date_of_file, None);
+        assert_eq!(header.step_names(), vec!["I", "II"]);
+        assert_eq!(header.step_quality[0], -9);
+        assert_eq!(header.unit_conversion[0], 4878);
+        assert_eq!(header.pacemaker, metadata::Pacemaker::SingleChamberBipolar);
+        assert_eq!(header.recorder, "digital");
+        assert_eq!(header.granularity, 200);
+        assert_eq!(header.proprietary, "acme");
+        assert_eq!(header.copyright, "");
+    }
+
+    #[test]
+    fn test_given_unknown_step_code_when_metadata_then_names_stay_with_their_steps() {
+        let mut buffer = header_buffer();
+        buffer[146] = 3;
+        buffer[148] = 5;
+        buffer[152] = 11;
+        buffer[172..174].copy_from_slice(&(-9_i16).to_le_bytes());
+        for (i, resolution) in [1000_i16, 2000, 3000].iter().enumerate() {
+            buffer[196 + i * 2..198 + i * 2].copy_from_slice(&resolution.to_le_bytes());
+        }
+        let metadata = metadata::Metadata::from(&Header::parse(&buffer).unwrap());
+        assert_eq!(metadata.steps, ["I", "Unknown", "V1"]);
+        assert_eq!(metadata.unit_conversion, [1000, 2000, 3000]);
+        assert_eq!(metadata.step_quality, [-9, 0, 0]);
+    }
+
+    #[test]
+    fn test_given_missing_file_when_read_chunk_then_chunk_read_error() {
+        assert!(matches!(
+            CarneAsadeFile::read_chunk(&Source::Plain("missing.dat".into()), 0, 10, 522),
+            Err(RecipeError::ChunkRead {
+                onset: 0,
+                offset: 10,
+                ..
+            })
+        ));
+    }
 
     #[test]
     fn test_given_empty_then_no_epics() {
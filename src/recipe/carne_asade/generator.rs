@@ -0,0 +1,359 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::metadata::Patient;
+
+use super::writer::CarneAsadaWriter;
+use super::Header;
+
+/// Samples per step written at a time, so long recordings stay out of memory.
+const BLOCK_SAMPLES: u64 = 4096;
+
+/// `(centre, width, amplitude)` of the P, Q, R, S and T waves, with centre and
+/// width as fractions of one beat and amplitude relative to the R wave.
+const ECG_WAVES: [(f64, f64, f64); 5] = [
+    (0.2, 0.025, 0.15),
+    (0.37, 0.01, -0.15),
+    (0.4, 0.01, 1.0),
+    (0.43, 0.01, -0.25),
+    (0.7, 0.05, 0.3),
+];
+
+/// Shape of the signal recorded on one step, in millivolts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Constant(f64),
+    Sine {
+        frequency: f64,
+        amplitude: f64,
+    },
+    Square {
+        frequency: f64,
+        amplitude: f64,
+    },
+    /// Gaussian P, QRS and T waves, the R peak `0.4` of a beat after its start.
+    Ecg {
+        heart_rate: f64,
+        amplitude: f64,
+    },
+    /// Uniform noise in `[-amplitude, amplitude]`, the same for a given seed.
+    Noise {
+        amplitude: f64,
+        seed: u64,
+    },
+}
+
+impl Waveform {
+    /// Value in millivolts at `index` of a recording sampled at `granularity`.
+    #[must_use]
+    pub fn value(&self, index: u64, granularity: u16) -> f64 {
+        #[allow(clippy::cast_precision_loss)]
+        let t = index as f64 / f64::from(granularity.max(1));
+        match *self {
+            Self::Constant(value) => value,
+            Self::Sine {
+                frequency,
+                amplitude,
+            } => amplitude * (2.0 * PI * frequency * t).sin(),
+            Self::Square {
+                frequency,
+                amplitude,
+            } => {
+                if (frequency * t).fract() < 0.5 {
+                    amplitude
+                } else {
+                    -amplitude
+                }
+            }
+            Self::Ecg {
+                heart_rate,
+                amplitude,
+            } => {
+                let phase = (t * heart_rate / 60.0).fract();
+                amplitude
+                    * ECG_WAVES
+                        .iter()
+                        .map(|(centre, width, height)| {
+                            height * (-((phase - centre) / width).powi(2) / 2.0).exp()
+                        })
+                        .sum::<f64>()
+            }
+            Self::Noise { amplitude, seed } => {
+                #[allow(clippy::cast_precision_loss)]
+                let unit = (splitmix64(seed ^ index) >> 11) as f64 / (1_u64 << 53) as f64;
+                amplitude * (2.0 * unit - 1.0)
+            }
+        }
+    }
+}
+
+/// One round of the SplitMix64 generator, enough to make noise reproducible
+/// sample by sample without carrying state from block to block.
+fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// A step of a generated recording: its code, resolution in nV per unit and
+/// the waveform it records.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeneratedStep {
+    pub code: usize,
+    pub unit_conversion: i16,
+    pub waveform: Waveform,
+}
+
+/// Produces CARNE1.0 recordings of any length with known sample values.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    pub granularity: u16,
+    pub duration: Duration,
+    pub start: chrono::NaiveDateTime,
+    pub steps: Vec<GeneratedStep>,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self {
+            granularity: 200,
+            duration: Duration::from_secs(10),
+            start: chrono::NaiveDate::from_ymd_opt(2008, 11, 5)
+                .and_then(|d| d.and_hms_opt(5, 9, 0))
+                .unwrap_or_default(),
+            steps: vec![GeneratedStep {
+                code: 5,
+                unit_conversion: 2500,
+                waveform: Waveform::Ecg {
+                    heart_rate: 60.0,
+                    amplitude: 1.0,
+                },
+            }],
+        }
+    }
+}
+
+impl Generator {
+    /// Number of samples per step.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        let millis = self.duration.as_millis() * u128::from(self.granularity) / 1000;
+        u64::try_from(millis).unwrap_or(u64::MAX)
+    }
+
+    #[must_use]
+    pub fn header(&self) -> Header {
+        let mut steps = [0; 12];
+        let mut unit_conversion = [0; 12];
+        for (i, step) in self.steps.iter().take(12).enumerate() {
+            steps[i] = step.code;
+            unit_conversion[i] = i32::from(step.unit_conversion);
+        }
+        Header {
+            variable_block_size: 0,
+            size: u32::try_from(self.size()).unwrap_or(u32::MAX),
+            variable_block_offset: 0,
+            sample_offset: 0,
+            file_version: 1,
+            patient: Patient::default(),
+            date_of_recipe: self.start.date(),
+            date_of_file: Some(self.start.date()),
+            time_of_recipe: self.start.time(),
+            number_of_steps: u16::try_from(self.steps.len()).unwrap_or(u16::MAX),
+            steps,
+            step_quality: [0; 12],
+            unit_conversion,
+            pacemaker: crate::metadata::Pacemaker::None,
+            recorder: String::from("taqueria generator"),
+            granularity: self.granularity,
+            proprietary: String::new(),
+            copyright: String::new(),
+        }
+    }
+
+    /// Raw values of `len` samples per step from sample `onset` on.
+    #[must_use]
+    pub fn samples(&self, onset: u64, len: u64) -> Vec<Vec<i16>> {
+        self.steps
+            .iter()
+            .map(|step| {
+                let scale = f64::from(step.unit_conversion) * 1e-6;
+                (onset..onset + len)
+                    .map(|i| {
+                        let raw = (step.waveform.value(i, self.granularity) / scale).round();
+                        #[allow(clippy::cast_possible_truncation)]
+                        let raw = raw.clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16;
+                        raw
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The millivolt values parsing the whole recording should produce.
+    #[must_use]
+    pub fn expected(&self) -> Vec<Vec<f64>> {
+        self.samples(0, self.size())
+            .iter()
+            .zip(&self.steps)
+            .map(|(raw, step)| {
+                raw.iter()
+                    .map(|v| f64::from(*v) * f64::from(step.unit_conversion) * 10_f64.powi(-6))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Writes the recording block by block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when there are more than twelve steps or the
+    /// recording cannot be written.
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<W> {
+        let mut carne = CarneAsadaWriter::new(writer, &self.header(), &[])?;
+        let size = self.size();
+        let mut onset = 0;
+        while onset < size {
+            let len = BLOCK_SAMPLES.min(size - onset);
+            carne.write_samples(&self.samples(onset, len))?;
+            onset += len;
+        }
+        carne.finish()
+    }
+
+    /// # Errors
+    ///
+    /// Returns an error when the file cannot be created or written.
+    pub fn write_to(&self, path: &Path) -> io::Result<()> {
+        let writer = self.write(BufWriter::new(File::create(path)?))?;
+        writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::{tempdir, NamedTempFile};
+
+    use super::*;
+    use crate::recipe::carne_asade::fixture;
+
+    fn parse(generator: &Generator) -> serde_json::Value {
+        let file = NamedTempFile::new().unwrap();
+        generator.write_to(file.path()).unwrap();
+        let dir = tempdir().unwrap();
+        let command = fixture::command(dir.path(), file.path());
+        let output = fixture::parse(&command).unwrap().payload.output;
+        serde_json::from_slice(&std::fs::read(output).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_given_generated_recording_when_parse_then_samples_match_expected() {
+        let generator = Generator {
+            granularity: 250,
+            duration: Duration::from_secs(300),
+            steps: vec![
+                GeneratedStep {
+                    code: 5,
+                    unit_conversion: 2500,
+                    waveform: Waveform::Ecg {
+                        heart_rate: 72.0,
+                        amplitude: 1.5,
+                    },
+                },
+                GeneratedStep {
+                    code: 6,
+                    unit_conversion: 1000,
+                    waveform: Waveform::Sine {
+                        frequency: 1.0,
+                        amplitude: 2.0,
+                    },
+                },
+                GeneratedStep {
+                    code: 11,
+                    unit_conversion: 5000,
+                    waveform: Waveform::Noise {
+                        amplitude: 0.1,
+                        seed: 7,
+                    },
+                },
+            ],
+            ..Generator::default()
+        };
+        let recipe = parse(&generator);
+        assert_eq!(recipe["steps"], serde_json::json!(["I", "II", "V1"]));
+        assert_eq!(recipe["size"], 75_000);
+        let guacamole: Vec<Vec<f64>> = serde_json::from_value(recipe["guacamole"].clone()).unwrap();
+        let expected = generator.expected();
+        assert_eq!(guacamole.len(), expected.len());
+        for (guac, expected) in guacamole.iter().zip(&expected) {
+            assert_eq!(guac.len(), expected.len());
+            assert!(guac.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-9));
+        }
+    }
+
+    #[test]
+    fn test_given_waveforms_when_sampled_then_known_values() {
+        let sine = Waveform::Sine {
+            frequency: 1.0,
+            amplitude: 2.0,
+        };
+        assert!((sine.value(50, 200) - 2.0).abs() < 1e-12);
+        let square = Waveform::Square {
+            frequency: 2.0,
+            amplitude: 1.0,
+        };
+        assert_eq!(square.value(0, 200), 1.0);
+        assert_eq!(square.value(50, 200), -1.0);
+        let ecg = Waveform::Ecg {
+            heart_rate: 60.0,
+            amplitude: 1.0,
+        };
+        let beat: Vec<f64> = (0..200).map(|i| ecg.value(i, 200)).collect();
+        let peak = (0..200)
+            .max_by(|a, b| beat[*a].total_cmp(&beat[*b]))
+            .unwrap();
+        assert_eq!(peak, 80);
+        assert_eq!(ecg.value(80, 200), ecg.value(280, 200));
+        let noise = Waveform::Noise {
+            amplitude: 0.5,
+            seed: 1,
+        };
+        assert_eq!(noise.value(9, 200), noise.value(9, 200));
+        assert_ne!(noise.value(9, 200), noise.value(10, 200));
+        assert!((0..1000).all(|i| noise.value(i, 200).abs() <= 0.5));
+    }
+
+    #[test]
+    fn test_given_values_beyond_range_when_samples_then_clamped() {
+        let generator = Generator {
+            steps: vec![GeneratedStep {
+                code: 5,
+                unit_conversion: 1000,
+                waveform: Waveform::Constant(-100.0),
+            }],
+            ..Generator::default()
+        };
+        assert_eq!(generator.samples(0, 2), vec![vec![i16::MIN, i16::MIN]]);
+    }
+
+    /// A four-hour, three-step recording; run with `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore = "writes and parses a multi-hour recording"]
+    fn test_given_multi_hour_recording_when_parse_then_all_samples_decoded() {
+        let generator = Generator {
+            duration: Duration::from_secs(4 * 60 * 60),
+            steps: vec![Generator::default().steps[0]; 3],
+            ..Generator::default()
+        };
+        let recipe = parse(&generator);
+        assert_eq!(recipe["size"], 2_880_000);
+    }
+}
@@ -0,0 +1,142 @@
+use std::fmt;
+use std::io::Cursor;
+
+use serde::Serialize;
+
+use crate::metadata::Metadata;
+use crate::recipe::source::Source;
+use crate::recipe::RecipeError;
+
+use super::{CarneAsada, Checksum, HEADER_END};
+
+/// What the header of a recording says, plus what follows from it, read
+/// without touching a single sample.
+#[derive(Serialize, Debug, Clone)]
+pub struct Inspection {
+    pub filepath: String,
+    pub checksum: Checksum,
+    pub start: chrono::NaiveDateTime,
+    pub end: chrono::NaiveDateTime,
+    /// `size` samples at `granularity` Hz, or `None` without a sample rate.
+    pub duration_ms: Option<u64>,
+    pub metadata: Metadata,
+}
+
+impl CarneAsada {
+    /// Reads the magic number and header of `filepath`, decompressing only
+    /// as much of an archive as that takes. A header checksum that does not
+    /// match, or is absent, is reported in [`Inspection::checksum`] rather
+    /// than as an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RecipeError`] when the file cannot be read or its header
+    /// is not a CARNE1.0 header.
+    pub fn inspect(filepath: &str) -> Result<Inspection, RecipeError> {
+        let head = Source::head(filepath, HEADER_END as usize)?;
+        let header = CarneAsada::read_header(&mut Cursor::new(&head), true)?;
+        let mut buffer = [0u8; 512];
+        buffer.copy_from_slice(&head[10..]);
+        let duration_ms = (header.granularity > 0)
+            .then(|| u64::from(header.size) * 1000 / u64::from(header.granularity));
+        Ok(Inspection {
+            filepath: String::from(filepath),
+            checksum: Checksum::of(u16::from_le_bytes([head[8], head[9]]), &buffer),
+            start: header.start(),
+            end: header.timestamp_of(u64::from(header.size)),
+            duration_ms,
+            metadata: Metadata::from(&header),
+        })
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metadata = &self.metadata;
+        let patient = &metadata.patient;
+        writeln!(f, "file:          {}", self.filepath)?;
+        writeln!(f, "checksum:      {}", self.checksum)?;
+        writeln!(f, "start:         {}", self.start)?;
+        writeln!(f, "end:           {}", self.end)?;
+        match self.duration_ms {
+            Some(ms) => writeln!(
+                f,
+                "duration:      {ms} ms ({:02}:{:02}:{:02}.{:03})",
+                ms / 3_600_000,
+                ms / 60_000 % 60,
+                ms / 1000 % 60,
+                ms % 1000
+            )?,
+            None => writeln!(f, "duration:      unknown")?,
+        }
+        writeln!(f, "sample rate:   {} Hz", metadata.granularity)?;
+        writeln!(f, "samples:       {} per step", metadata.size)?;
+        let steps: Vec<String> = metadata
+            .steps
+            .iter()
+            .zip(&metadata.unit_conversion)
+            .map(|(step, nv)| format!("{step} ({nv} nV)"))
+            .collect();
+        writeln!(
+            f,
+            "steps:         {} [{}]",
+            metadata.number_of_steps,
+            steps.join(", ")
+        )?;
+        writeln!(
+            f,
+            "patient:       {} {} ({}), {:?}, {:?}",
+            patient.first_name, patient.last_name, patient.id, patient.sex, patient.race
+        )?;
+        writeln!(f, "pacemaker:     {:?}", metadata.pacemaker)?;
+        write!(f, "recorder:      {}", metadata.recorder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+    use crate::recipe::carne_asade::fixture;
+
+    #[test]
+    fn test_given_recording_when_inspect_then_duration_derived_from_header() {
+        let recording =
+            fixture::recording(250, &[(5, 2500), (6, 5000)], &[vec![0; 625], vec![0; 625]]);
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&recording).unwrap();
+        let inspection = CarneAsada::inspect(file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(inspection.checksum, Checksum::Valid);
+        assert_eq!(inspection.duration_ms, Some(2500));
+        assert_eq!(inspection.end.to_string(), "2008-11-05 05:09:02.500");
+        assert_eq!(inspection.metadata.steps, ["I", "II"]);
+        let human = inspection.to_string();
+        assert!(human.contains("duration:      2500 ms (00:00:02.500)"));
+        assert!(human.contains("steps:         2 [I (2500 nV), II (5000 nV)]"));
+        let json = serde_json::to_value(&inspection).unwrap();
+        assert_eq!(json["duration_ms"], 2500);
+        assert_eq!(json["metadata"]["granularity"], 250);
+    }
+
+    #[test]
+    fn test_given_bad_checksum_when_inspect_then_reported_not_failed() {
+        let mut recording = fixture::recording(0, &[(5, 2500)], &[vec![0; 4]]);
+        recording[8] ^= 0xFF;
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&recording).unwrap();
+        let inspection = CarneAsada::inspect(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(inspection.checksum, Checksum::Mismatch);
+        assert_eq!(inspection.duration_ms, None);
+
+        recording[8..10].copy_from_slice(&[0, 0]);
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&recording).unwrap();
+        let inspection = CarneAsada::inspect(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(inspection.checksum, Checksum::Absent);
+        assert!(inspection.to_string().contains("checksum:      absent"));
+    }
+}
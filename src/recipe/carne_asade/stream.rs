@@ -0,0 +1,226 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+use crate::recipe::source::Source;
+use crate::recipe::RecipeError;
+
+use super::{CarneAsada, CarneAsadaGaucamole, Header, DTYPE, MAX_BYTES};
+
+/// Decoded samples for every step, starting at sample `onset` of the recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuacamoleBlock {
+    pub onset: u64,
+    pub timestamp: chrono::NaiveDateTime,
+    pub guacamole: Vec<Vec<f64>>,
+}
+
+/// Reads a CARNE1.0 recording block by block, holding at most one block of
+/// samples in memory and writing nothing to disk.
+pub struct CarneAsadaStream<R> {
+    reader: R,
+    header: Header,
+    chunks: std::vec::IntoIter<(u32, u32)>,
+    /// Keeps a decompressed archive on disk while it is read.
+    _source: Option<Source>,
+}
+
+impl CarneAsadaStream<BufReader<File>> {
+    /// # Errors
+    ///
+    /// Returns a [`RecipeError`] when the file cannot be opened or its header
+    /// is not a valid CARNE1.0 header.
+    pub fn open(filepath: &str, lenient: bool) -> Result<Self, RecipeError> {
+        let source = Source::open(filepath)?;
+        let file = source.reader().map_err(|e| RecipeError::Open {
+            path: source.path().to_path_buf(),
+            source: e,
+        })?;
+        Ok(Self {
+            _source: Some(source),
+            ..Self::new(BufReader::new(file), lenient)?
+        })
+    }
+}
+
+impl<R: Read + Seek> CarneAsadaStream<R> {
+    /// # Errors
+    ///
+    /// Returns a [`RecipeError`] when the header is not a valid CARNE1.0 header.
+    pub fn new(reader: R, lenient: bool) -> Result<Self, RecipeError> {
+        Self::with_block_bytes(reader, lenient, MAX_BYTES)
+    }
+
+    /// Like [`CarneAsadaStream::new`], reading roughly `block_bytes` of samples
+    /// per block, and at least one sample of every step.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RecipeError`] when the header is not a valid CARNE1.0 header,
+    /// or [`RecipeError::SamplesTooLarge`] when it declares more sample bytes
+    /// than can be addressed.
+    pub fn with_block_bytes(
+        mut reader: R,
+        lenient: bool,
+        block_bytes: u32,
+    ) -> Result<Self, RecipeError> {
+        let header = CarneAsada::read_header(&mut reader, lenient)?;
+        let step_count = u32::from(header.number_of_steps);
+        let total_bytes = header.sample_bytes()?;
+        let block_bytes = block_bytes.max(step_count * DTYPE);
+        let chunks: Vec<(u32, u32)> =
+            CarneAsada::calculate_chunks(total_bytes, block_bytes, 1, step_count, DTYPE)
+                .into_iter()
+                .flatten()
+                .collect();
+        Ok(Self {
+            reader,
+            header,
+            chunks: chunks.into_iter(),
+            _source: None,
+        })
+    }
+
+    #[must_use]
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    fn read_block(&mut self, onset: u32, offset: u32) -> Result<GuacamoleBlock, RecipeError> {
+        let chunk_error = |source| RecipeError::ChunkRead {
+            onset,
+            offset,
+            source,
+        };
+        let mut buffer = vec![0; (offset - onset) as usize];
+        self.reader
+            .seek(SeekFrom::Start(
+                u64::from(self.header.sample_start()) + u64::from(onset),
+            ))
+            .map_err(chunk_error)?;
+        self.reader.read_exact(&mut buffer).map_err(chunk_error)?;
+        let first_sample =
+            u64::from(onset) / (u64::from(self.header.number_of_steps) * u64::from(DTYPE));
+        Ok(GuacamoleBlock {
+            onset: first_sample,
+            timestamp: self.header.timestamp_of(first_sample),
+            guacamole: CarneAsadaGaucamole::decode(
+                &buffer,
+                self.header.number_of_steps,
+                &self.header.unit_conversion,
+            ),
+        })
+    }
+}
+
+impl<R: Read + Seek> Iterator for CarneAsadaStream<R> {
+    type Item = Result<GuacamoleBlock, RecipeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (onset, offset) = self.chunks.next()?;
+        let block = self.read_block(onset, offset);
+        if block.is_err() {
+            self.chunks = Vec::new().into_iter();
+        }
+        Some(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn recording(samples: &[[i16; 2]]) -> Vec<u8> {
+        let mut header = [0u8; 512];
+        header[4..8].copy_from_slice(&u32::try_from(samples.len()).unwrap().to_le_bytes());
+        header[128] = 5;
+        header[130] = 11;
+        header[132..134].copy_from_slice(&2008_u16.to_le_bytes());
+        header[140] = 5;
+        header[142] = 9;
+        header[146] = 2;
+        header[148] = 5;
+        header[150] = 6;
+        header[196..198].copy_from_slice(&1000_i16.to_le_bytes());
+        header[198..200].copy_from_slice(&2000_i16.to_le_bytes());
+        header[262] = 2;
+        let mut bytes = b"CARNE1.0".to_vec();
+        bytes.extend_from_slice(&Header::checksum(&header).to_le_bytes());
+        bytes.extend_from_slice(&header);
+        for sample in samples {
+            bytes.extend_from_slice(&sample[0].to_le_bytes());
+            bytes.extend_from_slice(&sample[1].to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_given_recording_when_stream_then_blocks_cover_all_samples() {
+        let bytes = recording(&[[1, -1], [2, -2], [3, -3], [4, -4], [5, -5]]);
+        let blocks: Vec<GuacamoleBlock> =
+            CarneAsadaStream::with_block_bytes(Cursor::new(bytes), false, 8)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(
+            blocks.iter().map(|b| b.onset).collect::<Vec<_>>(),
+            vec![0, 2, 4]
+        );
+        assert_eq!(
+            blocks[0].guacamole,
+            vec![vec![0.001, 0.002], vec![-0.002, -0.004]]
+        );
+        assert_eq!(blocks[2].guacamole, vec![vec![0.005], vec![-0.01]]);
+        assert_eq!(
+            blocks[1].timestamp,
+            chrono::NaiveDate::from_ymd_opt(2008, 11, 5)
+                .unwrap()
+                .and_hms_opt(5, 9, 1)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_given_block_smaller_than_frame_when_stream_then_one_sample_per_block() {
+        let bytes = recording(&[[1, -1], [2, -2], [3, -3]]);
+        let blocks: Vec<GuacamoleBlock> =
+            CarneAsadaStream::with_block_bytes(Cursor::new(bytes), false, 1)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(
+            blocks.iter().map(|b| b.onset).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_given_size_past_u32_bytes_when_stream_then_samples_too_large() {
+        let mut bytes = recording(&[[1, -1]]);
+        bytes[14..18].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut header = [0u8; 512];
+        header.copy_from_slice(&bytes[10..522]);
+        bytes[8..10].copy_from_slice(&Header::checksum(&header).to_le_bytes());
+        assert!(matches!(
+            CarneAsadaStream::new(Cursor::new(bytes), false),
+            Err(RecipeError::SamplesTooLarge {
+                bytes: 17_179_869_180
+            })
+        ));
+    }
+
+    #[test]
+    fn test_given_truncated_samples_when_stream_then_error_and_end() {
+        let mut bytes = recording(&[[1, -1], [2, -2], [3, -3], [4, -4]]);
+        bytes.truncate(bytes.len() - 6);
+        let mut stream = CarneAsadaStream::with_block_bytes(Cursor::new(bytes), false, 8).unwrap();
+        assert!(stream.next().unwrap().is_ok());
+        assert!(matches!(
+            stream.next(),
+            Some(Err(RecipeError::ChunkRead { onset: 8, .. }))
+        ));
+        assert!(stream.next().is_none());
+    }
+}
@@ -0,0 +1,406 @@
+use std::io::{self, Write};
+
+use super::{Header, CARNE_ASADA_MAGIC_NUMBER, HEADER_END};
+
+const UNKNOWN: i16 = -9;
+
+fn write_u16(buffer: &mut [u8; 512], at: usize, value: u16) {
+    buffer[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_i16(buffer: &mut [u8; 512], at: usize, value: i16) {
+    buffer[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(buffer: &mut [u8; 512], at: usize, value: u32) {
+    buffer[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Writes `value` NUL-padded, truncating it to the last whole character that
+/// fits in `len` bytes.
+fn write_string(buffer: &mut [u8; 512], at: usize, len: usize, value: &str) {
+    let end = value.floor_char_boundary(len);
+    buffer[at..at + end].copy_from_slice(&value.as_bytes()[..end]);
+}
+
+/// Writes day, month and year, or three `-9`s for an unknown date.
+fn write_date<D: chrono::Datelike>(buffer: &mut [u8; 512], at: usize, date: Option<D>) {
+    let (day, month, year) = date.map_or((UNKNOWN, UNKNOWN, UNKNOWN), |d| {
+        (
+            i16::try_from(d.day()).unwrap_or(UNKNOWN),
+            i16::try_from(d.month()).unwrap_or(UNKNOWN),
+            i16::try_from(d.year()).unwrap_or(UNKNOWN),
+        )
+    });
+    write_i16(buffer, at, day);
+    write_i16(buffer, at + 2, month);
+    write_i16(buffer, at + 4, year);
+}
+
+impl Header {
+    /// Encodes the fixed header, the inverse of [`Header::parse`] but for
+    /// text fields, which are read back without leading or trailing
+    /// whitespace.
+    #[must_use]
+    pub fn to_bytes(&self) -> [u8; 512] {
+        use chrono::Timelike;
+
+        let mut buffer = [0u8; 512];
+        write_u32(&mut buffer, 0, self.variable_block_size);
+        write_u32(&mut buffer, 4, self.size);
+        write_u32(&mut buffer, 8, self.variable_block_offset);
+        write_u32(&mut buffer, 12, self.sample_offset);
+        write_i16(&mut buffer, 16, self.file_version);
+        write_string(&mut buffer, 18, 40, &self.patient.first_name);
+        write_string(&mut buffer, 58, 40, &self.patient.last_name);
+        write_string(&mut buffer, 98, 20, &self.patient.id);
+        write_u16(&mut buffer, 118, self.patient.sex.into());
+        write_u16(&mut buffer, 120, self.patient.race.into());
+        write_date(&mut buffer, 122, self.patient.date_of_birth);
+        write_date(&mut buffer, 128, Some(self.date_of_recipe));
+        write_date(&mut buffer, 134, self.date_of_file);
+        for (i, value) in [
+            self.time_of_recipe.hour(),
+            self.time_of_recipe.minute(),
+            self.time_of_recipe.second(),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            write_u16(&mut buffer, 140 + i * 2, u16::try_from(value).unwrap_or(0));
+        }
+        write_u16(&mut buffer, 146, self.number_of_steps);
+        for i in 0..12 {
+            write_u16(
+                &mut buffer,
+                148 + i * 2,
+                u16::try_from(self.steps[i]).unwrap_or(0),
+            );
+            write_i16(&mut buffer, 172 + i * 2, self.step_quality[i]);
+            write_i16(
+                &mut buffer,
+                196 + i * 2,
+                i16::try_from(self.unit_conversion[i]).unwrap_or(UNKNOWN),
+            );
+        }
+        write_u16(&mut buffer, 220, self.pacemaker.into());
+        write_string(&mut buffer, 222, 40, &self.recorder);
+        write_u16(&mut buffer, 262, self.granularity);
+        write_string(&mut buffer, 264, 80, &self.proprietary);
+        write_string(&mut buffer, 344, 80, &self.copyright);
+        buffer
+    }
+}
+
+/// Writes a CARNE1.0 recording: magic number, header checksum, fixed header,
+/// variable-length block and then interleaved little-endian samples.
+///
+/// The header is written as given apart from the variable-length block size
+/// and the offsets, which are set to match the block actually written.
+pub struct CarneAsadaWriter<W> {
+    writer: W,
+    number_of_steps: usize,
+}
+
+impl<W: Write> CarneAsadaWriter<W> {
+    /// # Errors
+    ///
+    /// Returns an error when the header declares more than twelve steps or
+    /// it or the variable-length block cannot be written.
+    pub fn new(mut writer: W, header: &Header, variable_block: &[u8]) -> io::Result<Self> {
+        if usize::from(header.number_of_steps) > header.steps.len() {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        let block_size = u32::try_from(variable_block.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let header = Header {
+            variable_block_size: block_size,
+            variable_block_offset: HEADER_END,
+            sample_offset: HEADER_END + block_size,
+            ..header.clone()
+        };
+        let buffer = header.to_bytes();
+        writer.write_all(CARNE_ASADA_MAGIC_NUMBER.as_bytes())?;
+        writer.write_all(&Header::checksum(&buffer).to_le_bytes())?;
+        writer.write_all(&buffer)?;
+        writer.write_all(variable_block)?;
+        Ok(Self {
+            writer,
+            number_of_steps: usize::from(header.number_of_steps),
+        })
+    }
+
+    /// Interleaves one block of samples, one vector per step, and appends it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the number of steps does not match the header,
+    /// the steps hold different numbers of samples, or the samples cannot be
+    /// written.
+    pub fn write_samples(&mut self, samples: &[Vec<i16>]) -> io::Result<()> {
+        if samples.len() != self.number_of_steps {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        let size = samples.first().map_or(0, Vec::len);
+        if samples.iter().any(|step| step.len() != size) {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        let mut bytes = Vec::with_capacity(size * samples.len() * 2);
+        for i in 0..size {
+            for step in samples {
+                bytes.extend_from_slice(&step[i].to_le_bytes());
+            }
+        }
+        self.writer.write_all(&bytes)
+    }
+
+    /// # Errors
+    ///
+    /// Returns an error when the underlying writer cannot be flushed.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Writes a whole recording, taking its size and number of steps from
+    /// `samples`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the recording cannot be written.
+    pub fn write(
+        writer: W,
+        header: &Header,
+        variable_block: &[u8],
+        samples: &[Vec<i16>],
+    ) -> io::Result<W> {
+        let header = Header {
+            size: u32::try_from(samples.first().map_or(0, Vec::len))
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?,
+            number_of_steps: u16::try_from(samples.len())
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?,
+            ..header.clone()
+        };
+        let mut carne = Self::new(writer, &header, variable_block)?;
+        carne.write_samples(samples)?;
+        carne.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use proptest::prelude::*;
+    use tempfile::{tempdir, NamedTempFile};
+
+    use super::*;
+    use crate::metadata::{Pacemaker, Patient, Race, Sex};
+    use crate::recipe::carne_asade::{fixture, CarneAsada, CarneAsadeFile, NO_CHECKSUM};
+
+    fn blank_header(number_of_steps: u8) -> Header {
+        let mut buffer = [0u8; 512];
+        buffer[128] = 1;
+        buffer[130] = 1;
+        buffer[132..134].copy_from_slice(&2020_u16.to_le_bytes());
+        buffer[146] = number_of_steps;
+        Header::parse(&buffer).unwrap()
+    }
+
+    fn date() -> impl Strategy<Value = chrono::NaiveDate> {
+        (1900..2100_i32, 1..=12_u32, 1..=28_u32)
+            .prop_map(|(y, m, d)| chrono::NaiveDate::from_ymd_opt(y, m, d).unwrap())
+    }
+
+    /// Text without surrounding whitespace, which is not read back.
+    fn text(len: usize) -> impl Strategy<Value = String> {
+        proptest::string::string_regex(&format!("[A-Za-z0-9_.-]{{0,{len}}}")).unwrap()
+    }
+
+    prop_compose! {
+        fn header()(
+            file_version in any::<i16>(),
+            patient in (text(40), text(40), text(20), 0..3_u16, 0..4_u16, proptest::option::of(date())),
+            date_of_recipe in date(),
+            date_of_file in proptest::option::of(date()),
+            (hour, min, sec) in (0..24_u32, 0..60_u32, 0..60_u32),
+            steps in proptest::array::uniform12(0..20_usize),
+            step_quality in proptest::array::uniform12(any::<i16>()),
+            unit_conversion in proptest::array::uniform12(any::<i16>()),
+            pacemaker in 0..6_u16,
+            recorder in text(40),
+            granularity in any::<u16>(),
+            proprietary in text(80),
+            copyright in text(80),
+        ) -> Header {
+            Header {
+                variable_block_size: 0,
+                size: 0,
+                variable_block_offset: 0,
+                sample_offset: 0,
+                file_version,
+                patient: Patient {
+                    first_name: patient.0,
+                    last_name: patient.1,
+                    id: patient.2,
+                    sex: Sex::from(patient.3),
+                    race: Race::from(patient.4),
+                    date_of_birth: patient.5,
+                },
+                date_of_recipe,
+                date_of_file,
+                time_of_recipe: chrono::NaiveTime::from_hms_opt(hour, min, sec).unwrap(),
+                number_of_steps: 0,
+                steps,
+                step_quality,
+                unit_conversion: unit_conversion.map(i32::from),
+                pacemaker: Pacemaker::from(pacemaker),
+                recorder,
+                granularity,
+                proprietary,
+                copyright,
+            }
+        }
+    }
+
+    /// Up to a few chunks of samples, so parsing splits them across threads.
+    fn samples() -> impl Strategy<Value = Vec<Vec<i16>>> {
+        (1..=12_usize, 0..6000_usize).prop_flat_map(|(steps, size)| {
+            proptest::collection::vec(proptest::collection::vec(any::<i16>(), size), steps)
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_given_recording_when_written_then_parse_reproduces_it(
+            header in header(),
+            variable_block in proptest::collection::vec(any::<u8>(), 0..64),
+            samples in samples(),
+        ) {
+            let bytes =
+                CarneAsadaWriter::write(Vec::new(), &header, &variable_block, &samples).unwrap();
+            // A header whose checksum happens to be 0x0000 reads as having none.
+            prop_assume!(bytes[8..10] != NO_CHECKSUM.to_le_bytes());
+
+            let mut reader = Cursor::new(&bytes);
+            let parsed = CarneAsada::read_header(&mut reader, false).unwrap();
+            let block_size = u32::try_from(variable_block.len()).unwrap();
+            prop_assert_eq!(&parsed, &Header {
+                variable_block_size: block_size,
+                size: u32::try_from(samples[0].len()).unwrap(),
+                variable_block_offset: HEADER_END,
+                sample_offset: HEADER_END + block_size,
+                number_of_steps: u16::try_from(samples.len()).unwrap(),
+                ..header
+            });
+            prop_assert_eq!(
+                CarneAsadeFile::read_variable_block(&mut reader, &parsed).unwrap(),
+                variable_block
+            );
+
+            let mut file = NamedTempFile::new().unwrap();
+            file.write_all(&bytes).unwrap();
+            let dir = tempdir().unwrap();
+            let output = fixture::parse(&fixture::command(dir.path(), file.path()))
+                .unwrap()
+                .payload
+                .output;
+            let recipe: serde_json::Value =
+                serde_json::from_slice(&std::fs::read(output).unwrap()).unwrap();
+            let decoded: Vec<Vec<f64>> = serde_json::from_value(recipe["guacamole"].clone()).unwrap();
+            let expected: Vec<Vec<f64>> = samples
+                .iter()
+                .enumerate()
+                .map(|(step, values)| {
+                    values
+                        .iter()
+                        .map(|v| {
+                            f64::from(*v) * f64::from(parsed.unit_conversion[step]) * 10_f64.powi(-6)
+                        })
+                        .collect()
+                })
+                .collect();
+            prop_assert_eq!(decoded.len(), expected.len());
+            for (decoded, expected) in decoded.iter().zip(&expected) {
+                prop_assert_eq!(decoded.len(), expected.len());
+                prop_assert!(decoded.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-9));
+            }
+        }
+    }
+
+    #[test]
+    fn test_given_steps_of_different_lengths_when_write_then_invalid_input() {
+        let result =
+            CarneAsadaWriter::write(Vec::new(), &blank_header(2), b"", &[vec![1, 2], vec![1]]);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_given_padded_text_when_written_then_read_back_trimmed() {
+        let header = Header {
+            patient: Patient {
+                first_name: String::from("  Ana "),
+                ..Patient::default()
+            },
+            recorder: String::from("\tHolter 3 "),
+            ..blank_header(1)
+        };
+        let bytes = CarneAsadaWriter::write(Vec::new(), &header, b"", &[vec![1]]).unwrap();
+        let parsed = CarneAsada::read_header(&mut Cursor::new(&bytes), false).unwrap();
+        assert_eq!(parsed.patient.first_name, "Ana");
+        assert_eq!(parsed.recorder, "Holter 3");
+    }
+
+    #[test]
+    fn test_given_multi_byte_text_when_truncated_then_whole_characters_kept() {
+        let header = Header {
+            patient: Patient {
+                first_name: format!("ab{}", "€".repeat(13)),
+                ..Patient::default()
+            },
+            ..blank_header(1)
+        };
+        let bytes = CarneAsadaWriter::write(Vec::new(), &header, b"", &[vec![1]]).unwrap();
+        assert_eq!(&bytes[28 + 38..28 + 40], [0, 0]);
+        let parsed = CarneAsada::read_header(&mut Cursor::new(&bytes), false).unwrap();
+        assert_eq!(parsed.patient.first_name, format!("ab{}", "€".repeat(12)));
+    }
+
+    #[test]
+    fn test_given_written_recording_when_parse_then_recipe_holds_samples() {
+        let header = Header {
+            steps: [5, 6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            unit_conversion: [1000, 2000, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            granularity: 200,
+            ..blank_header(2)
+        };
+        let mut file = NamedTempFile::new().unwrap();
+        let bytes =
+            CarneAsadaWriter::write(Vec::new(), &header, b"note", &[vec![1, 2], vec![-1, -2]])
+                .unwrap();
+        file.write_all(&bytes).unwrap();
+        let dir = tempdir().unwrap();
+        let command = fixture::command(dir.path(), file.path());
+        let output = fixture::parse(&command).unwrap().payload.output;
+        let recipe: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(output).unwrap()).unwrap();
+        assert_eq!(recipe["steps"], serde_json::json!(["I", "II"]));
+        assert_eq!(recipe["variable_block_text"], "note");
+        assert_eq!(
+            recipe["guacamole"],
+            serde_json::json!([[0.001, 0.002], [-0.002, -0.004]])
+        );
+    }
+
+    #[test]
+    fn test_given_mismatched_steps_when_write_samples_then_invalid_input() {
+        let header = blank_header(2);
+        let mut writer = CarneAsadaWriter::new(Vec::new(), &header, &[]).unwrap();
+        assert_eq!(
+            writer.write_samples(&[vec![1]]).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+}
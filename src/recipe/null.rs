@@ -1,14 +1,22 @@
 use std::path::PathBuf;
 
-use crate::{command::Command, event::Event};
+use crate::{command::Command, event::Event, stage::Pipeline};
 
-use super::{ParseRecipe, Recipe, RecipeParsed};
+use super::{Confidence, ParseRecipe, Recipe, RecipeError, RecipeParsed};
 
 pub struct Null {}
 
 impl Recipe for Null {
-    fn parse(&self, _command: &Command<ParseRecipe>) -> Option<Event<RecipeParsed>> {
-        Some(Event {
+    fn probe(&self, _head: &[u8]) -> Confidence {
+        Confidence::Possible
+    }
+
+    fn parse(
+        &self,
+        _command: &Command<ParseRecipe>,
+        _pipeline: &mut Pipeline,
+    ) -> Result<Event<RecipeParsed>, RecipeError> {
+        Ok(Event {
             event_type: 0,
             payload: RecipeParsed {
                 output: PathBuf::from("."),
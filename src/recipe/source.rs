@@ -0,0 +1,264 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use flate2::read::MultiGzDecoder;
+use tempfile::NamedTempFile;
+use zip::ZipArchive;
+
+use super::RecipeError;
+
+const ZIP_MAGIC_NUMBER: [u8; 4] = *b"PK\x03\x04";
+const GZIP_MAGIC_NUMBER: [u8; 2] = [0x1f, 0x8b];
+/// Separates an archive from the member to read, as in `holter.zip#day1.dat`.
+const MEMBER_SEPARATOR: char = '#';
+
+/// The bytes of a recording, read straight from disk or, for `.zip` and `.gz`
+/// files, decompressed once into a temporary file that is removed on drop.
+///
+/// Every [`Source::reader`] is an independent handle, so threads can seek and
+/// read different chunks at the same time.
+#[derive(Debug)]
+pub enum Source {
+    Plain(PathBuf),
+    Spooled { path: PathBuf, spool: NamedTempFile },
+}
+
+impl Source {
+    /// Opens `filepath`, which may name a zip member after a `#`. A zip
+    /// archive without one must hold exactly one file.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::Open`] when the file cannot be read or
+    /// decompressed, and [`RecipeError::ArchiveMember`] when the member to
+    /// read cannot be chosen.
+    pub fn open(filepath: &str) -> Result<Self, RecipeError> {
+        let (path, file, kind) = locate(filepath)?;
+        let open_error = |source| RecipeError::Open {
+            path: path.clone(),
+            source,
+        };
+        let spool = match kind {
+            Kind::Plain => return Ok(Self::Plain(path)),
+            Kind::Gzip => spool(MultiGzDecoder::new(file)).map_err(open_error)?,
+            Kind::Zip(member) => {
+                let mut archive = ZipArchive::new(file).map_err(|e| open_error(e.into()))?;
+                let entry = by_name(&mut archive, &path, member.as_deref())?;
+                spool(entry).map_err(open_error)?
+            }
+        };
+        Ok(Self::Spooled { path, spool })
+    }
+
+    /// Up to `len` bytes from the start of the recording, decompressing only
+    /// as much of an archive as that takes.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Source::open`].
+    pub fn head(filepath: &str, len: usize) -> Result<Vec<u8>, RecipeError> {
+        let (path, mut file, kind) = locate(filepath)?;
+        let open_error = |source| RecipeError::Open {
+            path: path.clone(),
+            source,
+        };
+        let mut head = vec![0u8; len];
+        let read = match kind {
+            Kind::Plain => read_up_to(&mut file, &mut head),
+            Kind::Gzip => read_up_to(&mut MultiGzDecoder::new(file), &mut head),
+            Kind::Zip(member) => {
+                let mut archive = ZipArchive::new(file).map_err(|e| open_error(e.into()))?;
+                let mut entry = by_name(&mut archive, &path, member.as_deref())?;
+                read_up_to(&mut entry, &mut head)
+            }
+        }
+        .map_err(open_error)?;
+        head.truncate(read);
+        Ok(head)
+    }
+
+    /// The file as given, archive included.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Plain(path) | Self::Spooled { path, .. } => path,
+        }
+    }
+
+    /// # Errors
+    ///
+    /// Returns an error when the recording or its spool cannot be opened.
+    pub fn reader(&self) -> io::Result<File> {
+        match self {
+            Self::Plain(path) => File::open(path),
+            Self::Spooled { spool, .. } => spool.reopen(),
+        }
+    }
+}
+
+fn read_up_to(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match reader.read(&mut buffer[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+fn spool(mut reader: impl Read) -> io::Result<NamedTempFile> {
+    let mut spool = NamedTempFile::new()?;
+    io::copy(&mut reader, &mut spool)?;
+    Ok(spool)
+}
+
+enum Kind {
+    Plain,
+    Gzip,
+    Zip(Option<String>),
+}
+
+/// Splits off a zip member, opens the file and tells its kind from its first
+/// bytes, leaving it rewound.
+fn locate(filepath: &str) -> Result<(PathBuf, File, Kind), RecipeError> {
+    let (path, member) = match filepath.rsplit_once(MEMBER_SEPARATOR) {
+        Some((archive, member)) if !Path::new(filepath).exists() => {
+            (PathBuf::from(archive), Some(member.to_string()))
+        }
+        _ => (PathBuf::from(filepath), None),
+    };
+    let open_error = |source| RecipeError::Open {
+        path: path.clone(),
+        source,
+    };
+    let mut file = File::open(&path).map_err(open_error)?;
+    let mut magic = [0u8; 4];
+    let len = read_up_to(&mut file, &mut magic).map_err(open_error)?;
+    file.seek(SeekFrom::Start(0)).map_err(open_error)?;
+
+    let kind = if magic[..len] == ZIP_MAGIC_NUMBER {
+        Kind::Zip(member)
+    } else if member.is_some() {
+        return Err(RecipeError::ArchiveMember { path, member });
+    } else if magic[..len.min(2)] == GZIP_MAGIC_NUMBER {
+        Kind::Gzip
+    } else {
+        Kind::Plain
+    };
+    Ok((path, file, kind))
+}
+
+/// The named member, or the only file in the archive when none is named.
+fn by_name<'a>(
+    archive: &'a mut ZipArchive<File>,
+    path: &Path,
+    member: Option<&str>,
+) -> Result<zip::read::ZipFile<'a>, RecipeError> {
+    let member_error = || RecipeError::ArchiveMember {
+        path: path.to_path_buf(),
+        member: member.map(String::from),
+    };
+    let name = match member {
+        Some(member) => member.to_string(),
+        None => {
+            let mut files = archive
+                .file_names()
+                .filter(|name| !name.ends_with('/') && !name.starts_with("__MACOSX/"));
+            match (files.next(), files.next()) {
+                (Some(name), None) => name.to_string(),
+                _ => return Err(member_error()),
+            }
+        }
+    };
+    match archive.by_name(&name) {
+        Ok(entry) => Ok(entry),
+        Err(zip::result::ZipError::FileNotFound) => Err(member_error()),
+        Err(e) => Err(RecipeError::Open {
+            path: path.to_path_buf(),
+            source: e.into(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+
+    fn read_all(source: &Source) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        source.reader().unwrap().read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn archive(members: &[(&str, &[u8])]) -> NamedTempFile {
+        let file = NamedTempFile::new().unwrap();
+        let mut zip = zip::ZipWriter::new(file.reopen().unwrap());
+        for (name, bytes) in members {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(bytes).unwrap();
+        }
+        zip.finish().unwrap();
+        file
+    }
+
+    #[test]
+    fn test_given_plain_file_when_open_then_read_in_place() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"CARNE1.0").unwrap();
+        let source = Source::open(file.path().to_str().unwrap()).unwrap();
+        assert!(matches!(source, Source::Plain(_)));
+        assert_eq!(read_all(&source), b"CARNE1.0");
+    }
+
+    #[test]
+    fn test_given_gzip_file_when_open_then_decompressed() {
+        let file = NamedTempFile::new().unwrap();
+        let mut gz = GzEncoder::new(file.reopen().unwrap(), flate2::Compression::default());
+        gz.write_all(b"CARNE1.0 samples").unwrap();
+        gz.finish().unwrap();
+        let source = Source::open(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(read_all(&source), b"CARNE1.0 samples");
+        assert_eq!(
+            Source::head(file.path().to_str().unwrap(), 64).unwrap(),
+            b"CARNE1.0 samples"
+        );
+        assert_eq!(source.path(), file.path());
+    }
+
+    #[test]
+    fn test_given_zip_with_single_member_when_open_then_member_read() {
+        let file = archive(&[("holter/", b""), ("holter/day1.dat", b"day one")]);
+        let source = Source::open(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(read_all(&source), b"day one");
+    }
+
+    #[test]
+    fn test_given_zip_with_named_member_when_open_then_that_member_read() {
+        let file = archive(&[("day1.dat", b"day one"), ("day2.dat", b"day two")]);
+        let filepath = format!("{}#day2.dat", file.path().display());
+        let source = Source::open(&filepath).unwrap();
+        assert_eq!(read_all(&source), b"day two");
+        assert_eq!(Source::head(&filepath, 3).unwrap(), b"day");
+    }
+
+    #[test]
+    fn test_given_zip_with_several_members_when_none_named_then_error() {
+        let file = archive(&[("day1.dat", b"day one"), ("day2.dat", b"day two")]);
+        assert!(matches!(
+            Source::open(file.path().to_str().unwrap()),
+            Err(RecipeError::ArchiveMember { member: None, .. })
+        ));
+        let filepath = format!("{}#day3.dat", file.path().display());
+        assert!(matches!(
+            Source::open(&filepath),
+            Err(RecipeError::ArchiveMember { member: Some(m), .. }) if m == "day3.dat"
+        ));
+    }
+}
@@ -0,0 +1,124 @@
+pub mod beats;
+pub mod filter;
+pub mod leads;
+pub mod resample;
+pub mod rhythm;
+
+use crate::encoder::OutputEncoder;
+use crate::metadata::Metadata;
+use crate::recipe::RecipeError;
+use beats::{BeatDetector, Beats};
+
+/// Transforms decoded guacamole on its way from a parser to an encoder, one
+/// block of consecutive samples at a time.
+pub trait Stage {
+    /// Sees, and may change, the metadata the encoder is given: steps added,
+    /// a new sample rate, a new size.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RecipeError`] when the stage cannot apply to the recording.
+    fn begin(&mut self, metadata: &mut Metadata) -> Result<(), RecipeError>;
+    /// Transforms a block, one vector per step. Blocks arrive in order, and
+    /// the block returned may hold more or fewer samples than the one given.
+    fn process(&mut self, guacamole: Vec<Vec<f64>>) -> Vec<Vec<f64>>;
+    /// Samples still held back once the last block has been processed.
+    fn finish(&mut self) -> Vec<Vec<f64>> {
+        Vec::new()
+    }
+}
+
+/// Stages run in order between decoding and encoding, keeping count of the
+/// samples handed to the encoder, and a beat detector watching what the
+/// encoder is handed.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+    detector: Option<BeatDetector>,
+    onset: u64,
+}
+
+impl Pipeline {
+    #[must_use]
+    pub fn new(stages: Vec<Box<dyn Stage>>, detector: Option<BeatDetector>) -> Self {
+        Self {
+            stages,
+            detector,
+            onset: 0,
+        }
+    }
+
+    /// Whether samples can skip decoding and go to the encoder untouched.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty() && self.detector.is_none()
+    }
+
+    /// # Errors
+    ///
+    /// Returns the first error a stage returns.
+    pub fn begin(&mut self, metadata: &mut Metadata) -> Result<(), RecipeError> {
+        self.stages
+            .iter_mut()
+            .try_for_each(|stage| stage.begin(metadata))?;
+        match &mut self.detector {
+            Some(detector) => detector.begin(metadata),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs `guacamole` through every stage and encodes what comes out.
+    ///
+    /// # Errors
+    ///
+    /// Returns the encoder's error.
+    pub fn encode(
+        &mut self,
+        guacamole: Vec<Vec<f64>>,
+        encoder: &mut dyn OutputEncoder,
+    ) -> Result<(), RecipeError> {
+        let guacamole = self
+            .stages
+            .iter_mut()
+            .fold(guacamole, |guacamole, stage| stage.process(guacamole));
+        self.emit(&guacamole, encoder)
+    }
+
+    /// Flushes each stage in turn through the stages after it.
+    ///
+    /// # Errors
+    ///
+    /// Returns the encoder's error.
+    pub fn finish(&mut self, encoder: &mut dyn OutputEncoder) -> Result<(), RecipeError> {
+        for i in 0..self.stages.len() {
+            let tail = self.stages[i].finish();
+            let tail = self.stages[i + 1..]
+                .iter_mut()
+                .fold(tail, |guacamole, stage| stage.process(guacamole));
+            self.emit(&tail, encoder)?;
+        }
+        Ok(())
+    }
+
+    /// The beats found, once [`Pipeline::finish`] has run.
+    pub fn beats(&mut self) -> Option<Beats> {
+        self.detector.take().map(BeatDetector::finish)
+    }
+
+    fn emit(
+        &mut self,
+        guacamole: &[Vec<f64>],
+        encoder: &mut dyn OutputEncoder,
+    ) -> Result<(), RecipeError> {
+        let len = guacamole.first().map_or(0, Vec::len);
+        if len == 0 {
+            return Ok(());
+        }
+        if let Some(detector) = &mut self.detector {
+            detector.observe(guacamole);
+        }
+        encoder.encode(self.onset, guacamole)?;
+        self.onset += len as u64;
+        Ok(())
+    }
+}
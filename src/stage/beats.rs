@@ -0,0 +1,468 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::Metadata;
+use crate::recipe::{RecipeError, StepSelector};
+
+use super::filter::{steady_states, Biquad};
+
+pub const FILENAME: &str = "beats.json";
+
+/// Pass band, in Hz, that keeps the QRS complex and little of the P and T
+/// waves or of muscle noise.
+const QRS_BAND: (f64, f64) = (5.0, 15.0);
+/// Width of the moving-window integrator, in seconds.
+const INTEGRATION: f64 = 0.15;
+/// Seconds before an integrator peak searched for the R peak it follows.
+const SEARCH: f64 = 0.25;
+/// Seconds after a beat in which no other beat can occur.
+const REFRACTORY: f64 = 0.2;
+/// Seconds of signal the thresholds are first learnt from.
+const LEARNING: f64 = 2.0;
+/// RR intervals averaged to decide when a beat has been missed.
+const RR_HISTORY: usize = 8;
+/// How much longer than the average RR interval a gap may grow before the
+/// largest peak in it is taken as a missed beat.
+const SEARCH_BACK: f64 = 1.66;
+/// Seconds after a beat in which a peak with less than half its slope is
+/// taken for a T wave.
+const T_WAVE: f64 = 0.36;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Beat {
+    /// Index of the R peak among the samples of the output.
+    pub sample: u64,
+    pub time: chrono::NaiveDateTime,
+}
+
+/// R peaks found on one step of a parsed recording.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Beats {
+    pub step: String,
+    /// Sample rate of the output the beats index into.
+    pub granularity: u16,
+    pub beats: Vec<Beat>,
+}
+
+/// A peak of the integrated signal and the R peak it is taken to follow.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    r: u64,
+    value: f64,
+    /// Steepest slope within the integration window.
+    slope: f64,
+}
+
+/// Thresholds learnt over the first seconds, before any beat is decided.
+#[derive(Debug, Default)]
+struct Learning {
+    max: f64,
+    sum: f64,
+    count: u64,
+    candidates: Vec<Candidate>,
+}
+
+/// Finds R peaks on one step as its samples stream past, after Pan and
+/// Tompkins: band-pass, derivative, squaring and moving-window integration,
+/// then peaks of the integrated signal judged against adaptive signal and
+/// noise levels, with a search back for beats missed in a long gap.
+#[derive(Debug)]
+pub struct BeatDetector {
+    selector: StepSelector,
+    step: usize,
+    name: String,
+    granularity: u16,
+    start: chrono::NaiveDateTime,
+    band: Vec<Biquad>,
+    band_states: Option<Vec<[f64; 2]>>,
+    /// The last four band-passed samples, newest first.
+    derivative: [f64; 4],
+    window: VecDeque<f64>,
+    window_len: usize,
+    window_sum: f64,
+    /// The last samples of the step itself, to find R peaks in.
+    recent: VecDeque<f64>,
+    search_len: usize,
+    /// The integrated signal two samples and one sample ago.
+    integrated: [f64; 2],
+    /// Index of the next sample.
+    index: u64,
+    learning: Option<Learning>,
+    learning_len: u64,
+    signal_level: f64,
+    noise_level: f64,
+    refractory: u64,
+    rr: VecDeque<u64>,
+    missed: Option<Candidate>,
+    t_wave: u64,
+    /// Steepest slope of the last beat.
+    last_slope: f64,
+    beats: Vec<u64>,
+}
+
+/// `seconds` at `granularity` Hz, in whole samples, at least one.
+fn samples(seconds: f64, granularity: u16) -> usize {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let samples = (seconds * f64::from(granularity)).round() as usize;
+    samples.max(1)
+}
+
+impl BeatDetector {
+    #[must_use]
+    pub fn new(selector: StepSelector) -> Self {
+        Self {
+            selector,
+            step: 0,
+            name: String::new(),
+            granularity: 0,
+            start: chrono::NaiveDateTime::default(),
+            band: Vec::new(),
+            band_states: None,
+            derivative: [0.0; 4],
+            window: VecDeque::new(),
+            window_len: 1,
+            window_sum: 0.0,
+            recent: VecDeque::new(),
+            search_len: 1,
+            integrated: [0.0; 2],
+            index: 0,
+            learning: Some(Learning::default()),
+            learning_len: 0,
+            signal_level: 0.0,
+            noise_level: 0.0,
+            refractory: 0,
+            rr: VecDeque::new(),
+            missed: None,
+            t_wave: 0,
+            last_slope: 0.0,
+            beats: Vec::new(),
+        }
+    }
+
+    /// Finds the step to watch among those of the output and designs the
+    /// filters for its sample rate.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::UnknownStep`] when the output has no such step,
+    /// and [`RecipeError::InvalidFilter`] when its sample rate is too low to
+    /// hold the QRS band.
+    pub fn begin(&mut self, metadata: &Metadata) -> Result<(), RecipeError> {
+        let steps =
+            &metadata.steps[..usize::from(metadata.number_of_steps).min(metadata.steps.len())];
+        self.step = match &self.selector {
+            StepSelector::Index(index) => Some(*index).filter(|i| *i < steps.len()),
+            StepSelector::Name(name) => steps.iter().position(|s| s.eq_ignore_ascii_case(name)),
+        }
+        .ok_or_else(|| RecipeError::UnknownStep {
+            step: self.selector.clone(),
+            available: steps.to_vec(),
+        })?;
+        self.name.clone_from(&steps[self.step]);
+        let granularity = metadata.granularity;
+        let rate = f64::from(granularity);
+        let (low, high) = QRS_BAND;
+        if rate <= 2.0 * low * 1.2 {
+            return Err(RecipeError::InvalidFilter {
+                filter: format!("QRS band-pass {low}-{high} Hz"),
+                granularity,
+            });
+        }
+        self.band = vec![
+            Biquad::high_pass(low, rate),
+            Biquad::low_pass(high.min(rate * 0.45), rate),
+        ];
+        self.granularity = granularity;
+        self.start = metadata.date_of_recipe.and_time(metadata.time_of_recipe);
+        self.window_len = samples(INTEGRATION, granularity);
+        self.search_len = samples(SEARCH, granularity);
+        self.learning_len = samples(LEARNING, granularity) as u64;
+        self.refractory = samples(REFRACTORY, granularity) as u64;
+        self.t_wave = samples(T_WAVE, granularity) as u64;
+        Ok(())
+    }
+
+    /// Takes in the next block of the output, one vector per step.
+    pub fn observe(&mut self, guacamole: &[Vec<f64>]) {
+        let Some(samples) = guacamole.get(self.step) else {
+            return;
+        };
+        for x in samples {
+            self.push(*x);
+        }
+    }
+
+    fn push(&mut self, x: f64) {
+        let states = self
+            .band_states
+            .get_or_insert_with(|| steady_states(&self.band, x));
+        let filtered = self
+            .band
+            .iter()
+            .zip(states.iter_mut())
+            .fold(x, |x, (section, state)| section.step(state, x));
+        let [d1, d2, d3, d4] = self.derivative;
+        let slope = (2.0 * filtered + d1 - d3 - 2.0 * d4) / 8.0;
+        self.derivative = [filtered, d1, d2, d3];
+
+        self.window.push_back(slope * slope);
+        self.window_sum += slope * slope;
+        if self.window.len() > self.window_len {
+            self.window_sum -= self.window.pop_front().unwrap_or(0.0);
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let integrated = self.window_sum.max(0.0) / self.window_len as f64;
+        self.recent.push_back(x);
+        if self.recent.len() > self.search_len {
+            self.recent.pop_front();
+        }
+
+        let [before, previous] = self.integrated;
+        if previous > before && previous >= integrated {
+            let candidate = Candidate {
+                r: self.r_peak(),
+                value: previous,
+                slope: self.window.iter().copied().fold(0.0, f64::max).sqrt(),
+            };
+            self.judge(candidate);
+        }
+        self.integrated = [previous, integrated];
+        if let Some(learning) = &mut self.learning {
+            learning.max = learning.max.max(integrated);
+            learning.sum += integrated;
+            learning.count += 1;
+        }
+        self.index += 1;
+        if self.index == self.learning_len {
+            self.learnt();
+        }
+        self.search_back();
+    }
+
+    /// Index of the sample in the search window furthest from the window's
+    /// mean, so inverted complexes are found as well as upright ones.
+    fn r_peak(&self) -> u64 {
+        #[allow(clippy::cast_precision_loss)]
+        let mean = self.recent.iter().sum::<f64>() / self.recent.len().max(1) as f64;
+        let offset = self
+            .recent
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| (*a - mean).abs().total_cmp(&(*b - mean).abs()))
+            .map_or(0, |(i, _)| i);
+        self.index + 1 + offset as u64 - self.recent.len() as u64
+    }
+
+    fn threshold(&self) -> f64 {
+        self.noise_level + 0.25 * (self.signal_level - self.noise_level)
+    }
+
+    fn judge(&mut self, candidate: Candidate) {
+        if let Some(learning) = &mut self.learning {
+            learning.candidates.push(candidate);
+            return;
+        }
+        let last = self.beats.last().copied();
+        let t_wave = last.is_some_and(|last| {
+            candidate.r < last + self.t_wave && candidate.slope < self.last_slope / 2.0
+        });
+        if t_wave || last.is_some_and(|last| candidate.r < last + self.refractory) {
+            self.noise_level = 0.125 * candidate.value + 0.875 * self.noise_level;
+            return;
+        }
+        if candidate.value > self.threshold() {
+            self.signal_level = 0.125 * candidate.value + 0.875 * self.signal_level;
+            self.accept(candidate);
+        } else {
+            self.noise_level = 0.125 * candidate.value + 0.875 * self.noise_level;
+            if candidate.value > self.threshold() / 2.0
+                && self
+                    .missed
+                    .is_none_or(|missed| candidate.value > missed.value)
+            {
+                self.missed = Some(candidate);
+            }
+        }
+    }
+
+    fn accept(&mut self, candidate: Candidate) {
+        if let Some(last) = self.beats.last() {
+            self.rr.push_back(candidate.r - last);
+            if self.rr.len() > RR_HISTORY {
+                self.rr.pop_front();
+            }
+        }
+        self.beats.push(candidate.r);
+        self.last_slope = candidate.slope;
+        self.missed = None;
+    }
+
+    /// Takes the largest peak since the last beat as a missed beat once the
+    /// gap has grown well past the usual RR interval.
+    fn search_back(&mut self) {
+        let (Some(last), Some(missed)) = (self.beats.last(), self.missed) else {
+            return;
+        };
+        if self.rr.is_empty() {
+            return;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let average = self.rr.iter().sum::<u64>() as f64 / self.rr.len() as f64;
+        #[allow(clippy::cast_precision_loss)]
+        let gap = (self.index - last) as f64;
+        if gap > SEARCH_BACK * average {
+            self.signal_level = 0.25 * missed.value + 0.75 * self.signal_level;
+            self.accept(missed);
+        }
+    }
+
+    /// Sets the signal and noise levels from the learning period, then
+    /// judges the peaks seen during it.
+    fn learnt(&mut self) {
+        let Some(learning) = self.learning.take() else {
+            return;
+        };
+        #[allow(clippy::cast_precision_loss)]
+        let mean = learning.sum / learning.count.max(1) as f64;
+        self.signal_level = learning.max / 3.0;
+        self.noise_level = mean / 2.0;
+        for candidate in learning.candidates {
+            self.judge(candidate);
+        }
+    }
+
+    /// The beats found, timed from the first sample of the output.
+    #[must_use]
+    pub fn finish(mut self) -> Beats {
+        self.learnt();
+        let nanos = |sample: u64| {
+            let nanos = u128::from(sample) * 1_000_000_000 / u128::from(self.granularity.max(1));
+            chrono::Duration::nanoseconds(i64::try_from(nanos).unwrap_or(i64::MAX))
+        };
+        Beats {
+            step: self.name,
+            granularity: self.granularity,
+            beats: self
+                .beats
+                .iter()
+                .map(|sample| Beat {
+                    sample: *sample,
+                    time: self.start + nanos(*sample),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::{tempdir, NamedTempFile};
+
+    use super::*;
+    use crate::command::CommandHandler;
+    use crate::recipe::carne_asade::fixture;
+    use crate::recipe::carne_asade::generator::{GeneratedStep, Generator, Waveform};
+
+    /// A minute of ECG at 72 beats per minute on a wandering baseline.
+    fn ecg() -> Vec<f64> {
+        let ecg = Waveform::Ecg {
+            heart_rate: 72.0,
+            amplitude: 1.2,
+        };
+        let wander = Waveform::Sine {
+            frequency: 0.3,
+            amplitude: 0.4,
+        };
+        (0..12_000)
+            .map(|i| ecg.value(i, 200) + wander.value(i, 200))
+            .collect()
+    }
+
+    fn detect(signal: &[f64], block: usize) -> Beats {
+        let mut detector = BeatDetector::new(StepSelector::Index(0));
+        detector
+            .begin(&Metadata {
+                number_of_steps: 1,
+                steps: vec![String::from("II")],
+                granularity: 200,
+                ..Metadata::default()
+            })
+            .unwrap();
+        for block in signal.chunks(block) {
+            detector.observe(&[block.to_vec()]);
+        }
+        detector.finish()
+    }
+
+    #[test]
+    fn test_given_ecg_when_detected_then_every_r_peak_found() {
+        let beats = detect(&ecg(), 4096);
+        assert_eq!(beats.step, "II");
+        assert_eq!(beats.beats.len(), 72);
+        for (k, beat) in beats.beats.iter().enumerate() {
+            #[allow(clippy::cast_precision_loss)]
+            let expected = (k as f64 + 0.4) * 200.0 * 60.0 / 72.0;
+            #[allow(clippy::cast_precision_loss)]
+            let error = (beat.sample as f64 - expected).abs();
+            assert!(error <= 1.0, "beat {k} at {} not {expected}", beat.sample);
+        }
+        assert_eq!(beats.beats[1].time.to_string(), "1970-01-01 00:00:01.165");
+    }
+
+    #[test]
+    fn test_given_any_block_sizes_when_detected_then_same_beats() {
+        let signal = ecg();
+        let whole = detect(&signal, signal.len());
+        for block in [1, 250, 1000] {
+            assert_eq!(detect(&signal, block), whole);
+        }
+    }
+
+    #[test]
+    fn test_given_beats_step_when_parse_then_beats_written_and_reported() {
+        let generator = Generator {
+            duration: Duration::from_secs(30),
+            steps: vec![
+                GeneratedStep {
+                    code: 5,
+                    unit_conversion: 2500,
+                    waveform: Waveform::Constant(0.0),
+                },
+                GeneratedStep {
+                    code: 6,
+                    unit_conversion: 2500,
+                    waveform: Waveform::Ecg {
+                        heart_rate: 60.0,
+                        amplitude: 1.0,
+                    },
+                },
+            ],
+            ..Generator::default()
+        };
+        let file = NamedTempFile::new().unwrap();
+        generator.write_to(file.path()).unwrap();
+        let dir = tempdir().unwrap();
+        let mut command = fixture::command(dir.path(), file.path());
+        command.payload.options.beats = Some(StepSelector::Name(String::from("ii")));
+        let (sender, events) = std::sync::mpsc::channel();
+        let mut handler = fixture::handler();
+        handler.on_beats_detected(sender);
+        let parsed = handler.handle(&command).unwrap().payload;
+
+        let detected = events.try_recv().unwrap().payload;
+        assert_eq!(detected.step, "II");
+        assert_eq!(detected.beats, 30);
+        assert_eq!(detected.output, parsed.output.with_file_name(FILENAME));
+        assert!(!parsed
+            .output
+            .with_file_name(crate::stage::rhythm::FILENAME)
+            .exists());
+        let beats: Beats =
+            serde_json::from_slice(&std::fs::read(&detected.output).unwrap()).unwrap();
+        assert_eq!(beats.beats[0].sample, 80);
+        assert_eq!(beats.beats[0].time.to_string(), "2008-11-05 05:09:00.400");
+    }
+}
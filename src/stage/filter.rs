@@ -0,0 +1,444 @@
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::Metadata;
+use crate::recipe::RecipeError;
+
+use super::Stage;
+
+/// How far a filter's impulse response must have decayed before the
+/// backward pass is cut short at a block boundary.
+const SETTLED: f64 = 1e-9;
+
+/// A filter to apply to every step, designed for the recording's sample
+/// rate once it is known.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Filter {
+    /// Butterworth high-pass removing baseline wander below `cutoff` Hz.
+    HighPass { cutoff: f64 },
+    /// Removes mains hum at `frequency` Hz and its first `harmonics`
+    /// multiples below half the sample rate, each over a band `frequency / q`
+    /// wide.
+    Notch {
+        frequency: f64,
+        #[serde(default = "default_q")]
+        q: f64,
+        #[serde(default)]
+        harmonics: u32,
+    },
+}
+
+/// Wide enough to follow mains drifting by a fraction of a hertz, narrow
+/// enough to leave the QRS complex alone.
+#[must_use]
+pub fn default_q() -> f64 {
+    30.0
+}
+
+impl Filter {
+    /// Second-order sections for a recording sampled at `granularity` Hz.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RecipeError::InvalidFilter`] when the filter's frequencies
+    /// are not between zero and half the sample rate.
+    pub fn design(self, granularity: u16) -> Result<Vec<Biquad>, RecipeError> {
+        let rate = f64::from(granularity);
+        let invalid = || RecipeError::InvalidFilter {
+            filter: self.to_string(),
+            granularity,
+        };
+        match self {
+            Self::HighPass { cutoff } => {
+                if !(cutoff > 0.0 && cutoff < rate / 2.0) {
+                    return Err(invalid());
+                }
+                Ok(vec![Biquad::high_pass(cutoff, rate)])
+            }
+            Self::Notch {
+                frequency,
+                q,
+                harmonics,
+            } => {
+                if !(frequency > 0.0 && frequency < rate / 2.0 && q > 0.0) {
+                    return Err(invalid());
+                }
+                Ok((1..=harmonics + 1)
+                    .map(|k| f64::from(k) * frequency)
+                    .take_while(|f| *f < rate / 2.0)
+                    .map(|f| Biquad::notch(f, q, rate))
+                    .collect())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HighPass { cutoff } => write!(f, "high-pass {cutoff} Hz"),
+            Self::Notch {
+                frequency,
+                q,
+                harmonics: 0,
+            } => write!(f, "notch {frequency} Hz (Q {q})"),
+            Self::Notch {
+                frequency,
+                q,
+                harmonics,
+            } => write!(f, "notch {frequency} Hz (Q {q}, {harmonics} harmonics)"),
+        }
+    }
+}
+
+/// A second-order IIR section in transposed direct form II, normalised so
+/// that `a0` is one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+impl Biquad {
+    fn normalised(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self {
+            b: b.map(|b| b / a[0]),
+            a: [a[1] / a[0], a[2] / a[0]],
+        }
+    }
+
+    /// Butterworth high-pass with its -3 dB point at `cutoff` Hz, from the
+    /// bilinear transform with prewarping.
+    #[must_use]
+    pub fn high_pass(cutoff: f64, rate: f64) -> Self {
+        let w0 = 2.0 * PI * cutoff / rate;
+        let alpha = w0.sin() / (2.0 * FRAC_1_SQRT_2);
+        let cos = w0.cos();
+        Self::normalised(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Butterworth low-pass with its -3 dB point at `cutoff` Hz.
+    #[must_use]
+    pub fn low_pass(cutoff: f64, rate: f64) -> Self {
+        let w0 = 2.0 * PI * cutoff / rate;
+        let alpha = w0.sin() / (2.0 * FRAC_1_SQRT_2);
+        let cos = w0.cos();
+        Self::normalised(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Second-order notch centred on `frequency` Hz, `frequency / q` wide
+    /// between its -3 dB points.
+    #[must_use]
+    pub fn notch(frequency: f64, q: f64, rate: f64) -> Self {
+        let w0 = 2.0 * PI * frequency / rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        Self::normalised(
+            [1.0, -2.0 * cos, 1.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    fn dc_gain(&self) -> f64 {
+        (self.b[0] + self.b[1] + self.b[2]) / (1.0 + self.a[0] + self.a[1])
+    }
+
+    /// State the section settles in after a long run of `x`, so a signal
+    /// starting at `x` causes no transient.
+    fn steady_state(&self, x: f64) -> [f64; 2] {
+        let y = self.dc_gain() * x;
+        let z2 = self.b[2] * x - self.a[1] * y;
+        [self.b[1] * x - self.a[0] * y + z2, z2]
+    }
+
+    pub(crate) fn step(&self, z: &mut [f64; 2], x: f64) -> f64 {
+        let y = self.b[0] * x + z[0];
+        z[0] = self.b[1] * x - self.a[0] * y + z[1];
+        z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+
+    /// Magnitude of the slowest-decaying pole.
+    fn pole_radius(&self) -> f64 {
+        let [a1, a2] = self.a;
+        let discriminant = a1 * a1 - 4.0 * a2;
+        if discriminant < 0.0 {
+            a2.sqrt()
+        } else {
+            let root = discriminant.sqrt();
+            ((-a1 + root) / 2.0).abs().max(((-a1 - root) / 2.0).abs())
+        }
+    }
+}
+
+/// States of a cascade of sections settled on a constant `x`.
+pub(crate) fn steady_states(sections: &[Biquad], mut x: f64) -> Vec<[f64; 2]> {
+    sections
+        .iter()
+        .map(|section| {
+            let state = section.steady_state(x);
+            x *= section.dc_gain();
+            state
+        })
+        .collect()
+}
+
+/// Samples of one step filtered forwards but not yet backwards.
+#[derive(Debug, Default)]
+struct Channel {
+    forward: Option<Vec<[f64; 2]>>,
+    pending: Vec<f64>,
+}
+
+/// Applies a [`Filter`] forwards and then backwards, as `filtfilt` does, so
+/// the output has no phase shift and the filter's magnitude response squared.
+///
+/// The forward pass carries its state from block to block. The backward pass
+/// needs samples that have not arrived yet, so each step holds back enough
+/// of them for the filter's response to settle; samples reach the encoder
+/// that much later, and the rest are flushed by [`Stage::finish`]. Both
+/// passes start as if the signal had held its value before it began, so a
+/// high-pass brings the very last sample to zero.
+#[derive(Debug)]
+pub struct ZeroPhase {
+    filter: Filter,
+    sections: Vec<Biquad>,
+    /// Samples held back per step.
+    latency: usize,
+    channels: Vec<Channel>,
+}
+
+impl ZeroPhase {
+    #[must_use]
+    pub fn new(filter: Filter) -> Self {
+        Self {
+            filter,
+            sections: Vec::new(),
+            latency: 0,
+            channels: Vec::new(),
+        }
+    }
+
+    fn forward(&self, channel: &mut Channel, samples: &[f64]) {
+        let Some(&first) = samples.first() else {
+            return;
+        };
+        let states = channel
+            .forward
+            .get_or_insert_with(|| steady_states(&self.sections, first));
+        channel.pending.extend(samples.iter().map(|x| {
+            self.sections
+                .iter()
+                .zip(states.iter_mut())
+                .fold(*x, |x, (section, state)| section.step(state, x))
+        }));
+    }
+
+    /// The first `len` pending samples filtered backwards from the last one.
+    fn backward(&self, channel: &mut Channel, len: usize) -> Vec<f64> {
+        let Some(&last) = channel.pending.last() else {
+            return Vec::new();
+        };
+        let mut states = steady_states(&self.sections, last);
+        let mut output: Vec<f64> = channel
+            .pending
+            .iter()
+            .rev()
+            .map(|x| {
+                self.sections
+                    .iter()
+                    .zip(states.iter_mut())
+                    .fold(*x, |x, (section, state)| section.step(state, x))
+            })
+            .collect();
+        output.reverse();
+        output.truncate(len);
+        channel.pending.drain(..len);
+        output
+    }
+}
+
+impl Stage for ZeroPhase {
+    fn begin(&mut self, metadata: &mut Metadata) -> Result<(), RecipeError> {
+        self.sections = self.filter.design(metadata.granularity)?;
+        metadata.filters.push(self.filter);
+        let radius = self
+            .sections
+            .iter()
+            .map(Biquad::pole_radius)
+            .fold(0.0, f64::max);
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let latency = (SETTLED.ln() / radius.ln()).ceil().max(0.0) as usize;
+        self.latency = latency;
+        self.channels = (0..metadata.number_of_steps)
+            .map(|_| Channel::default())
+            .collect();
+        Ok(())
+    }
+
+    fn process(&mut self, guacamole: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        let mut channels = std::mem::take(&mut self.channels);
+        let output = channels
+            .iter_mut()
+            .zip(&guacamole)
+            .map(|(channel, samples)| {
+                self.forward(channel, samples);
+                let ready = channel.pending.len().saturating_sub(self.latency);
+                self.backward(channel, ready)
+            })
+            .collect();
+        self.channels = channels;
+        output
+    }
+
+    fn finish(&mut self) -> Vec<Vec<f64>> {
+        let mut channels = std::mem::take(&mut self.channels);
+        let output = channels
+            .iter_mut()
+            .map(|channel| {
+                let len = channel.pending.len();
+                self.backward(channel, len)
+            })
+            .collect();
+        self.channels = channels;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(granularity: u16) -> Metadata {
+        Metadata {
+            number_of_steps: 1,
+            granularity,
+            ..Metadata::default()
+        }
+    }
+
+    /// Filters `signal`, sampled at 200 Hz, handed over in blocks of `block`
+    /// samples.
+    fn filtered(filter: Filter, signal: &[f64], block: usize) -> Vec<f64> {
+        let mut stage = ZeroPhase::new(filter);
+        stage.begin(&mut metadata(200)).unwrap();
+        let mut output = Vec::new();
+        for block in signal.chunks(block) {
+            output.extend(stage.process(vec![block.to_vec()]).remove(0));
+        }
+        output.extend(stage.finish().remove(0));
+        output
+    }
+
+    fn sine(frequency: f64, len: usize) -> Vec<f64> {
+        #[allow(clippy::cast_precision_loss)]
+        (0..len)
+            .map(|i| (2.0 * PI * frequency * i as f64 / 200.0).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_given_drifting_signal_when_high_passed_then_drift_removed_without_phase_shift() {
+        let beat = sine(5.0, 12_000);
+        let signal: Vec<f64> = sine(0.05, 12_000)
+            .iter()
+            .zip(&beat)
+            .map(|(drift, beat)| 3.0 * drift + beat + 1.5)
+            .collect();
+        let output = filtered(Filter::HighPass { cutoff: 0.5 }, &signal, 17_000);
+
+        assert_eq!(output.len(), signal.len());
+        let error = output[2000..10_000]
+            .iter()
+            .zip(&beat[2000..10_000])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max);
+        assert!(error < 0.01, "largest error {error}");
+    }
+
+    #[test]
+    fn test_given_any_block_sizes_when_filtered_then_same_output() {
+        let signal: Vec<f64> = sine(0.3, 5000)
+            .iter()
+            .zip(sine(7.0, 5000))
+            .map(|(a, b)| a + b)
+            .collect();
+        let whole = filtered(Filter::HighPass { cutoff: 0.5 }, &signal, signal.len());
+        for block in [1, 97, 1024] {
+            let blocks = filtered(Filter::HighPass { cutoff: 0.5 }, &signal, block);
+            assert_eq!(blocks.len(), whole.len());
+            assert!(blocks.iter().zip(&whole).all(|(a, b)| (a - b).abs() < 1e-6));
+        }
+    }
+
+    #[test]
+    fn test_given_mains_hum_and_harmonic_when_notched_then_hum_removed() {
+        let beat = sine(1.2, 12_000);
+        let signal: Vec<f64> = beat
+            .iter()
+            .zip(sine(25.0, 12_000))
+            .zip(sine(50.0, 12_000))
+            .map(|((beat, hum), harmonic)| beat + 0.5 * hum + 0.2 * harmonic)
+            .collect();
+        let notch = Filter::Notch {
+            frequency: 25.0,
+            q: default_q(),
+            harmonics: 1,
+        };
+        let output = filtered(notch, &signal, 4096);
+
+        let error = output[2000..10_000]
+            .iter()
+            .zip(&beat[2000..10_000])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max);
+        assert!(error < 0.01, "largest error {error}");
+    }
+
+    #[test]
+    fn test_given_harmonics_past_nyquist_when_design_then_skipped() {
+        let notch = Filter::Notch {
+            frequency: 60.0,
+            q: 30.0,
+            harmonics: 3,
+        };
+        assert_eq!(notch.design(200).unwrap().len(), 1);
+        assert_eq!(notch.design(500).unwrap().len(), 4);
+        assert_eq!(notch.to_string(), "notch 60 Hz (Q 30, 3 harmonics)");
+    }
+
+    #[test]
+    fn test_given_filters_when_begin_then_chain_recorded_in_metadata() {
+        let mut metadata = metadata(200);
+        let high_pass = Filter::HighPass { cutoff: 0.5 };
+        let notch: Filter = serde_json::from_str(r#"{"type": "notch", "frequency": 50}"#).unwrap();
+        ZeroPhase::new(high_pass).begin(&mut metadata).unwrap();
+        ZeroPhase::new(notch).begin(&mut metadata).unwrap();
+        assert_eq!(
+            serde_json::to_value(&metadata.filters).unwrap(),
+            serde_json::json!([
+                {"type": "high-pass", "cutoff": 0.5},
+                {"type": "notch", "frequency": 50.0, "q": 30.0, "harmonics": 0}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_given_cutoff_beyond_nyquist_when_begin_then_invalid_filter() {
+        let mut stage = ZeroPhase::new(Filter::HighPass { cutoff: 120.0 });
+        let err = stage.begin(&mut metadata(200)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot apply high-pass 120 Hz to a recording sampled at 200 Hz"
+        );
+    }
+}
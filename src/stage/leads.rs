@@ -0,0 +1,183 @@
+use log::warn;
+
+use crate::metadata::Metadata;
+use crate::recipe::RecipeError;
+
+use super::Stage;
+
+/// A limb lead computed from leads I and II, named as in a recording's
+/// header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerivedLead {
+    /// Einthoven: III = II - I.
+    III,
+    /// Goldberger: aVR = -(I + II) / 2.
+    VR,
+    /// Goldberger: aVL = I - II / 2.
+    VL,
+    /// Goldberger: aVF = II - I / 2.
+    VF,
+}
+
+impl DerivedLead {
+    pub const ALL: [Self; 4] = [Self::III, Self::VR, Self::VL, Self::VF];
+
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::III => "III",
+            Self::VR => "VR",
+            Self::VL => "VL",
+            Self::VF => "VF",
+        }
+    }
+
+    /// Value from leads I and II, all in mV.
+    #[must_use]
+    pub fn value(self, i: f64, ii: f64) -> f64 {
+        match self {
+            Self::III => ii - i,
+            Self::VR => -(i + ii) / 2.0,
+            Self::VL => i - ii / 2.0,
+            Self::VF => ii - i / 2.0,
+        }
+    }
+
+    /// Resolution, in nV, at which every value computed from leads I and II
+    /// recorded at `i` and `ii` still fits the recorder's 16 bits: each
+    /// lead's resolution weighted as in [`DerivedLead::value`].
+    #[must_use]
+    pub fn resolution(self, i: i32, ii: i32) -> i32 {
+        let half = |r: i32| r / 2 + r % 2;
+        match self {
+            Self::III => i.saturating_add(ii),
+            Self::VR => half(i.saturating_add(ii)),
+            Self::VL => i.saturating_add(half(ii)),
+            Self::VF => ii.saturating_add(half(i)),
+        }
+    }
+}
+
+/// Appends III, aVR, aVL and aVF to recordings holding leads I and II,
+/// skipping any lead that was recorded, and leaves other recordings as they
+/// are.
+#[derive(Debug, Default)]
+pub struct DerivedLeads {
+    /// Positions of leads I and II, once found.
+    sources: Option<(usize, usize)>,
+    leads: Vec<DerivedLead>,
+}
+
+impl Stage for DerivedLeads {
+    fn begin(&mut self, metadata: &mut Metadata) -> Result<(), RecipeError> {
+        let position = |name: &str| metadata.steps.iter().position(|step| step == name);
+        let (Some(i), Some(ii)) = (position("I"), position("II")) else {
+            warn!("leads I and II are needed to derive the limb leads; none derived");
+            return Ok(());
+        };
+        self.sources = Some((i, ii));
+        self.leads = DerivedLead::ALL
+            .into_iter()
+            .filter(|lead| position(lead.name()).is_none())
+            .collect();
+        let (resolution_i, resolution_ii) =
+            (metadata.unit_conversion[i], metadata.unit_conversion[ii]);
+        for lead in &self.leads {
+            metadata.steps.push(lead.name().to_string());
+            metadata
+                .unit_conversion
+                .push(lead.resolution(resolution_i, resolution_ii));
+            metadata.step_quality.push(0);
+            metadata.derived_steps.push(lead.name().to_string());
+        }
+        metadata.number_of_steps += u16::try_from(self.leads.len()).unwrap_or(0);
+        Ok(())
+    }
+
+    fn process(&mut self, mut guacamole: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        let Some((i, ii)) = self.sources else {
+            return guacamole;
+        };
+        for lead in &self.leads {
+            let derived = guacamole[i]
+                .iter()
+                .zip(&guacamole[ii])
+                .map(|(i, ii)| lead.value(*i, *ii))
+                .collect();
+            guacamole.push(derived);
+        }
+        guacamole
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(steps: &[&str]) -> Metadata {
+        Metadata {
+            number_of_steps: u16::try_from(steps.len()).unwrap(),
+            steps: steps.iter().map(ToString::to_string).collect(),
+            unit_conversion: (1..=steps.len())
+                .map(|i| i32::try_from(i * 1000).unwrap())
+                .collect(),
+            step_quality: vec![0; steps.len()],
+            ..Metadata::default()
+        }
+    }
+
+    #[test]
+    fn test_given_leads_i_and_ii_when_processed_then_limb_leads_appended() {
+        let mut stage = DerivedLeads::default();
+        let mut metadata = metadata(&["II", "V1", "I"]);
+        stage.begin(&mut metadata).unwrap();
+        assert_eq!(metadata.steps, ["II", "V1", "I", "III", "VR", "VL", "VF"]);
+        assert_eq!(metadata.derived_steps, ["III", "VR", "VL", "VF"]);
+        assert_eq!(metadata.number_of_steps, 7);
+        assert_eq!(metadata.unit_conversion[3..], [4000, 2000, 3500, 2500]);
+
+        let guacamole = stage.process(vec![vec![0.5, -1.0], vec![9.0, 9.0], vec![1.0, 0.5]]);
+        assert_eq!(guacamole.len(), 7);
+        assert_eq!(guacamole[3], [-0.5, -1.5]);
+        assert_eq!(guacamole[4], [-0.75, 0.25]);
+        assert_eq!(guacamole[5], [0.75, 1.0]);
+        assert_eq!(guacamole[6], [0.0, -1.25]);
+    }
+
+    #[test]
+    fn test_given_full_scale_leads_when_derived_then_fit_sixteen_bits() {
+        let (resolution_i, resolution_ii) = (3000, 1001);
+        let full_scale = |resolution: i32| f64::from(i16::MAX) * f64::from(resolution) * 1e-6;
+        for lead in DerivedLead::ALL {
+            let scale = f64::from(lead.resolution(resolution_i, resolution_ii)) * 1e-6;
+            for (i, ii) in [(1.0, 1.0), (1.0, -1.0), (-1.0, 1.0), (-1.0, -1.0)] {
+                let value =
+                    lead.value(i * full_scale(resolution_i), ii * full_scale(resolution_ii));
+                assert!(
+                    (value / scale).round().abs() <= f64::from(i16::MAX),
+                    "{} overflows at {value} mV",
+                    lead.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_given_recorded_lead_when_begin_then_not_derived_again() {
+        let mut stage = DerivedLeads::default();
+        let mut metadata = metadata(&["I", "II", "III"]);
+        stage.begin(&mut metadata).unwrap();
+        assert_eq!(metadata.derived_steps, ["VR", "VL", "VF"]);
+        assert_eq!(stage.process(vec![vec![0.0]; 3]).len(), 6);
+    }
+
+    #[test]
+    fn test_given_no_lead_ii_when_processed_then_unchanged() {
+        let mut stage = DerivedLeads::default();
+        let mut metadata = metadata(&["I", "V1"]);
+        stage.begin(&mut metadata).unwrap();
+        assert_eq!(metadata.number_of_steps, 2);
+        assert!(metadata.derived_steps.is_empty());
+        assert_eq!(stage.process(vec![vec![1.0], vec![2.0]]).len(), 2);
+    }
+}
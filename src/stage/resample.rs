@@ -0,0 +1,279 @@
+use std::f64::consts::PI;
+
+use crate::metadata::Metadata;
+use crate::recipe::RecipeError;
+
+use super::Stage;
+
+/// Zero crossings of the interpolating sinc kept on either side of its peak.
+const ZERO_CROSSINGS: u64 = 16;
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Samples of one step not yet needed by any output sample.
+#[derive(Debug, Default)]
+struct Channel {
+    history: Vec<f64>,
+    /// Index of `history[0]` among the step's input samples.
+    first: u64,
+}
+
+impl Channel {
+    /// Input sample `index`, holding the first and last values beyond the
+    /// ends of the recording.
+    fn at(&self, index: i64) -> f64 {
+        let Ok(index) = u64::try_from(index) else {
+            return self.history.first().copied().unwrap_or(0.0);
+        };
+        let index = usize::try_from(index.saturating_sub(self.first)).unwrap_or(usize::MAX);
+        self.history
+            .get(index)
+            .or(self.history.last())
+            .copied()
+            .unwrap_or(0.0)
+    }
+}
+
+/// Converts every step to another sample rate with a polyphase windowed-sinc
+/// filter, rather than dropping or repeating samples.
+///
+/// Output sample `n` falls exactly on time `n / rate`, so the first output
+/// sample is the first input sample's instant. Each needs the input samples
+/// up to half the filter's length after it, so output lags input by that
+/// many samples until [`Stage::finish`].
+#[derive(Debug)]
+pub struct Resample {
+    rate: u16,
+    /// Upsampling factor.
+    up: u64,
+    /// Downsampling factor.
+    down: u64,
+    /// Input samples on either side of an output sample that contribute.
+    reach: u64,
+    /// Weights of input samples `-reach..=reach` around an output sample,
+    /// for each of the `up` positions it can fall on between two of them.
+    phases: Vec<Vec<f64>>,
+    channels: Vec<Channel>,
+    /// Input samples per step seen so far.
+    seen: u64,
+    /// Output samples per step produced so far.
+    produced: u64,
+}
+
+impl Resample {
+    #[must_use]
+    pub fn new(rate: u16) -> Self {
+        Self {
+            rate,
+            up: 1,
+            down: 1,
+            reach: 0,
+            phases: Vec::new(),
+            channels: Vec::new(),
+            seen: 0,
+            produced: 0,
+        }
+    }
+
+    /// Designs the low-pass that both interpolates and keeps what lies above
+    /// the lower of the two Nyquist frequencies from aliasing, split into
+    /// one set of weights per phase.
+    fn design(&mut self) {
+        let half = ZERO_CROSSINGS * self.up.max(self.down);
+        self.reach = half.div_ceil(self.up);
+        #[allow(clippy::cast_precision_loss)]
+        let (up, half_len) = (self.up as f64, half as f64);
+        #[allow(clippy::cast_precision_loss)]
+        let cutoff = 0.5 / self.up.max(self.down) as f64;
+        let kernel = |i: f64| {
+            if i.abs() > half_len {
+                return 0.0;
+            }
+            let sinc = if i == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * PI * cutoff * i).sin() / (PI * i)
+            };
+            let window =
+                0.42 + 0.5 * (PI * i / half_len).cos() + 0.08 * (2.0 * PI * i / half_len).cos();
+            sinc * window
+        };
+        let reach = i64::try_from(self.reach).unwrap_or(i64::MAX);
+        self.phases = (0..self.up)
+            .map(|phase| {
+                #[allow(clippy::cast_precision_loss)]
+                let weights: Vec<f64> = (-reach..=reach)
+                    .map(|d| kernel(phase as f64 - d as f64 * up))
+                    .collect();
+                let sum: f64 = weights.iter().sum();
+                weights.iter().map(|w| w / sum).collect()
+            })
+            .collect();
+    }
+
+    /// Output samples whose inputs have all been seen, or every output
+    /// sample up to the end of the input when `last`.
+    fn drain(&mut self, last: bool) -> Vec<Vec<f64>> {
+        let mut output = vec![Vec::new(); self.channels.len()];
+        loop {
+            let position = self.produced * self.down;
+            let (base, phase) = (position / self.up, position % self.up);
+            let ready = if last {
+                base < self.seen
+            } else {
+                base + self.reach < self.seen
+            };
+            if !ready {
+                break;
+            }
+            let start = i64::try_from(base).unwrap_or(i64::MAX)
+                - i64::try_from(self.reach).unwrap_or(i64::MAX);
+            let weights = &self.phases[usize::try_from(phase).unwrap_or(0)];
+            for (channel, output) in self.channels.iter().zip(output.iter_mut()) {
+                output.push(
+                    weights
+                        .iter()
+                        .zip(start..)
+                        .map(|(w, j)| w * channel.at(j))
+                        .sum(),
+                );
+            }
+            self.produced += 1;
+        }
+        let keep_from = (self.produced * self.down / self.up).saturating_sub(self.reach);
+        for channel in &mut self.channels {
+            if keep_from > channel.first {
+                let drop = usize::try_from(keep_from - channel.first)
+                    .unwrap_or(usize::MAX)
+                    .min(channel.history.len().saturating_sub(1));
+                channel.history.drain(..drop);
+                channel.first += drop as u64;
+            }
+        }
+        output
+    }
+}
+
+impl Stage for Resample {
+    fn begin(&mut self, metadata: &mut Metadata) -> Result<(), RecipeError> {
+        if metadata.granularity == 0 || self.rate == 0 {
+            return Err(RecipeError::InvalidRate {
+                from: metadata.granularity,
+                to: self.rate,
+            });
+        }
+        let (from, to) = (u64::from(metadata.granularity), u64::from(self.rate));
+        let divisor = gcd(from, to);
+        self.up = to / divisor;
+        self.down = from / divisor;
+        self.design();
+        self.channels = (0..metadata.number_of_steps)
+            .map(|_| Channel::default())
+            .collect();
+        let size = (u64::from(metadata.size) * self.up).div_ceil(self.down);
+        metadata.size = u32::try_from(size).unwrap_or(u32::MAX);
+        metadata.granularity = self.rate;
+        Ok(())
+    }
+
+    fn process(&mut self, guacamole: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        if self.up == self.down {
+            return guacamole;
+        }
+        let len = guacamole.first().map_or(0, Vec::len);
+        for (channel, samples) in self.channels.iter_mut().zip(guacamole) {
+            channel.history.extend(samples);
+        }
+        self.seen += len as u64;
+        self.drain(false)
+    }
+
+    fn finish(&mut self) -> Vec<Vec<f64>> {
+        if self.up == self.down {
+            return Vec::new();
+        }
+        self.drain(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, rate: f64, len: usize) -> Vec<f64> {
+        #[allow(clippy::cast_precision_loss)]
+        (0..len)
+            .map(|i| (2.0 * PI * frequency * i as f64 / rate).sin())
+            .collect()
+    }
+
+    /// Resamples `signal` handed over in blocks of `block` samples.
+    fn resampled(from: u16, to: u16, signal: &[f64], block: usize) -> (Metadata, Vec<f64>) {
+        let mut metadata = Metadata {
+            number_of_steps: 1,
+            granularity: from,
+            size: u32::try_from(signal.len()).unwrap(),
+            ..Metadata::default()
+        };
+        let mut stage = Resample::new(to);
+        stage.begin(&mut metadata).unwrap();
+        let mut output = Vec::new();
+        for block in signal.chunks(block) {
+            output.extend(stage.process(vec![block.to_vec()]).remove(0));
+        }
+        output.extend(stage.finish().into_iter().flatten());
+        (metadata, output)
+    }
+
+    #[test]
+    fn test_given_200_hz_when_resampled_to_250_hz_then_same_signal_on_new_grid() {
+        let (metadata, output) = resampled(200, 250, &sine(5.0, 200.0, 2000), 333);
+        assert_eq!(metadata.granularity, 250);
+        assert_eq!(metadata.size, 2500);
+        assert_eq!(output.len(), 2500);
+        let expected = sine(5.0, 250.0, 2500);
+        let error = output[100..2400]
+            .iter()
+            .zip(&expected[100..2400])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max);
+        assert!(error < 1e-3, "largest error {error}");
+    }
+
+    #[test]
+    fn test_given_any_block_sizes_when_resampled_then_same_output() {
+        let signal: Vec<f64> = sine(3.0, 360.0, 1000)
+            .iter()
+            .zip(sine(40.0, 360.0, 1000))
+            .map(|(a, b)| a + b)
+            .collect();
+        let (_, whole) = resampled(360, 250, &signal, signal.len());
+        assert_eq!(whole.len(), 695);
+        for block in [1, 7, 128] {
+            assert_eq!(resampled(360, 250, &signal, block).1, whole);
+        }
+    }
+
+    #[test]
+    fn test_given_tone_above_new_nyquist_when_downsampled_then_not_aliased() {
+        let (_, output) = resampled(500, 250, &sine(200.0, 500.0, 5000), 1024);
+        assert_eq!(output.len(), 2500);
+        let peak = output[100..2400]
+            .iter()
+            .map(|v| v.abs())
+            .fold(0.0, f64::max);
+        assert!(peak < 0.01, "aliased peak {peak}");
+    }
+
+    #[test]
+    fn test_given_same_rate_when_resampled_then_untouched() {
+        let signal = sine(1.0, 250.0, 10);
+        assert_eq!(resampled(250, 250, &signal, 4).1, signal);
+    }
+}
@@ -0,0 +1,321 @@
+use chrono::{DurationRound, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use super::beats::Beats;
+
+pub const FILENAME: &str = "rhythm.json";
+
+/// RR intervals, centred on each one, whose median gives the rate episodes
+/// are judged on, so one missed or extra beat does not start or end one.
+const MEDIAN_INTERVALS: usize = 5;
+
+/// Rates, in beats per minute, outside of which the rhythm is reported.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Thresholds {
+    /// Below this rate the rhythm is bradycardic.
+    pub bradycardia: f64,
+    /// Above this rate the rhythm is tachycardic.
+    pub tachycardia: f64,
+    /// Shortest run, in seconds, reported as an episode.
+    pub min_seconds: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            bradycardia: 50.0,
+            tachycardia: 100.0,
+            min_seconds: 30.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MinuteRate {
+    /// Wall-clock minute the rate covers.
+    pub start: chrono::NaiveDateTime,
+    /// Beats that fall in the minute.
+    pub beats: usize,
+    /// From the RR intervals ending in the minute; `None` without any.
+    pub bpm: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EpisodeKind {
+    Bradycardia,
+    Tachycardia,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Episode {
+    pub kind: EpisodeKind,
+    /// The beat the first interval of the episode starts on.
+    pub start: chrono::NaiveDateTime,
+    /// The beat the last interval of the episode ends on.
+    pub end: chrono::NaiveDateTime,
+    pub duration_ms: i64,
+    pub min_bpm: f64,
+    pub max_bpm: f64,
+}
+
+/// The heart-rate trend and rate episodes of one step's beats.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rhythm {
+    pub step: String,
+    pub thresholds: Thresholds,
+    pub minutes: Vec<MinuteRate>,
+    pub episodes: Vec<Episode>,
+}
+
+/// Median of `values`, which must not be empty.
+fn median(values: &mut [u64]) -> u64 {
+    values.sort_unstable();
+    values[values.len() / 2]
+}
+
+impl Rhythm {
+    #[must_use]
+    pub fn new(beats: &Beats, thresholds: Thresholds) -> Self {
+        let granularity = f64::from(beats.granularity.max(1));
+        #[allow(clippy::cast_precision_loss)]
+        let bpm = |rr: u64| 60.0 * granularity / rr.max(1) as f64;
+        let rr: Vec<u64> = beats
+            .beats
+            .windows(2)
+            .map(|pair| pair[1].sample - pair[0].sample)
+            .collect();
+        let minute = |time: chrono::NaiveDateTime| {
+            time.duration_trunc(TimeDelta::minutes(1)).unwrap_or(time)
+        };
+
+        let mut minutes: Vec<MinuteRate> = Vec::new();
+        let mut intervals: Vec<Vec<u64>> = Vec::new();
+        if let (Some(first), Some(last)) = (beats.beats.first(), beats.beats.last()) {
+            let mut start = minute(first.time);
+            while start <= last.time {
+                minutes.push(MinuteRate {
+                    start,
+                    beats: 0,
+                    bpm: None,
+                });
+                intervals.push(Vec::new());
+                start += TimeDelta::minutes(1);
+            }
+        }
+        let origin = minutes.first().map(|m| m.start);
+        let index_of = |time| {
+            origin.map_or(0, |origin| {
+                usize::try_from((minute(time) - origin).num_minutes()).unwrap_or(0)
+            })
+        };
+        for beat in &beats.beats {
+            minutes[index_of(beat.time)].beats += 1;
+        }
+        for (beat, rr) in beats.beats.iter().skip(1).zip(&rr) {
+            intervals[index_of(beat.time)].push(*rr);
+        }
+        for (minute, rr) in minutes.iter_mut().zip(&intervals) {
+            if !rr.is_empty() {
+                #[allow(clippy::cast_precision_loss)]
+                let mean = rr.iter().sum::<u64>() as f64 / rr.len() as f64;
+                minute.bpm = Some(60.0 * granularity / mean);
+            }
+        }
+
+        let half = MEDIAN_INTERVALS / 2;
+        let rates: Vec<f64> = (0..rr.len())
+            .map(|i| {
+                let window = &rr[i.saturating_sub(half)..(i + half + 1).min(rr.len())];
+                bpm(median(&mut window.to_vec()))
+            })
+            .collect();
+        let kind = |rate: f64| {
+            if rate < thresholds.bradycardia {
+                Some(EpisodeKind::Bradycardia)
+            } else if rate > thresholds.tachycardia {
+                Some(EpisodeKind::Tachycardia)
+            } else {
+                None
+            }
+        };
+        let mut episodes = Vec::new();
+        let mut i = 0;
+        while i < rates.len() {
+            let Some(current) = kind(rates[i]) else {
+                i += 1;
+                continue;
+            };
+            let run = rates[i..]
+                .iter()
+                .take_while(|rate| kind(**rate) == Some(current))
+                .count();
+            let (start, end) = (beats.beats[i].time, beats.beats[i + run].time);
+            let duration_ms = (end - start).num_milliseconds();
+            #[allow(clippy::cast_precision_loss)]
+            if duration_ms as f64 >= thresholds.min_seconds * 1000.0 {
+                let rates = &rates[i..i + run];
+                episodes.push(Episode {
+                    kind: current,
+                    start,
+                    end,
+                    duration_ms,
+                    min_bpm: rates.iter().copied().fold(f64::INFINITY, f64::min),
+                    max_bpm: rates.iter().copied().fold(0.0, f64::max),
+                });
+            }
+            i += run;
+        }
+
+        Self {
+            step: beats.step.clone(),
+            thresholds,
+            minutes,
+            episodes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::{tempdir, NamedTempFile};
+
+    use super::*;
+    use crate::command::CommandHandler;
+    use crate::recipe::carne_asade::fixture;
+    use crate::recipe::carne_asade::generator::{GeneratedStep, Generator, Waveform};
+    use crate::recipe::StepSelector;
+    use crate::stage::beats::Beat;
+
+    /// Beats at 4 Hz from 05:09:30, each run of `(bpm, seconds)` after the
+    /// last.
+    fn beats(runs: &[(u64, u64)]) -> Beats {
+        let start = chrono::NaiveDate::from_ymd_opt(2008, 11, 5)
+            .unwrap()
+            .and_hms_opt(5, 9, 30)
+            .unwrap();
+        let mut samples = vec![0];
+        for (bpm, seconds) in runs {
+            let rr = 240 / bpm;
+            for _ in 0..seconds * bpm / 60 {
+                samples.push(samples.last().unwrap() + rr);
+            }
+        }
+        Beats {
+            step: String::from("II"),
+            granularity: 4,
+            beats: samples
+                .into_iter()
+                .map(|sample| Beat {
+                    sample,
+                    time: start + TimeDelta::milliseconds(i64::try_from(sample * 250).unwrap()),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_given_beats_when_rhythm_then_rate_per_wall_clock_minute() {
+        let rhythm = Rhythm::new(&beats(&[(60, 90), (120, 60)]), Thresholds::default());
+        let minutes: Vec<(String, usize, Option<f64>)> = rhythm
+            .minutes
+            .iter()
+            .map(|m| {
+                (
+                    m.start.time().to_string(),
+                    m.beats,
+                    m.bpm.map(|bpm| (bpm * 10.0).round() / 10.0),
+                )
+            })
+            .collect();
+        assert_eq!(
+            minutes,
+            [
+                (String::from("05:09:00"), 30, Some(60.0)),
+                (String::from("05:10:00"), 60, Some(60.0)),
+                (String::from("05:11:00"), 120, Some(119.0)),
+                (String::from("05:12:00"), 1, Some(120.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_given_slow_and_fast_runs_when_rhythm_then_long_enough_ones_are_episodes() {
+        let rhythm = Rhythm::new(
+            &beats(&[(60, 60), (40, 60), (60, 60), (120, 20), (60, 60), (240, 45)]),
+            Thresholds::default(),
+        );
+        let episodes: Vec<(EpisodeKind, String, String, f64)> = rhythm
+            .episodes
+            .iter()
+            .map(|e| {
+                (
+                    e.kind,
+                    e.start.time().to_string(),
+                    e.end.time().to_string(),
+                    e.min_bpm,
+                )
+            })
+            .collect();
+        assert_eq!(
+            episodes,
+            [
+                (
+                    EpisodeKind::Bradycardia,
+                    String::from("05:10:30"),
+                    String::from("05:11:30"),
+                    40.0
+                ),
+                (
+                    EpisodeKind::Tachycardia,
+                    String::from("05:13:50"),
+                    String::from("05:14:35"),
+                    240.0
+                ),
+            ]
+        );
+        assert_eq!(rhythm.episodes[0].duration_ms, 60_000);
+    }
+
+    #[test]
+    fn test_given_rhythm_asked_for_when_parse_then_report_written_and_reported() {
+        let generator = Generator {
+            duration: Duration::from_secs(30),
+            steps: vec![GeneratedStep {
+                code: 6,
+                unit_conversion: 2500,
+                waveform: Waveform::Ecg {
+                    heart_rate: 60.0,
+                    amplitude: 1.0,
+                },
+            }],
+            ..Generator::default()
+        };
+        let file = NamedTempFile::new().unwrap();
+        generator.write_to(file.path()).unwrap();
+        let dir = tempdir().unwrap();
+        let mut command = fixture::command(dir.path(), file.path());
+        command.payload.options.beats = Some(StepSelector::Index(0));
+        command.payload.options.rhythm = Some(Thresholds {
+            bradycardia: 70.0,
+            min_seconds: 10.0,
+            ..Thresholds::default()
+        });
+        let (sender, events) = std::sync::mpsc::channel();
+        let mut handler = fixture::handler();
+        handler.on_rhythm_reported(sender);
+        let parsed = handler.handle(&command).unwrap().payload;
+
+        let reported = events.try_recv().unwrap().payload;
+        assert_eq!(reported.output, parsed.output.with_file_name(FILENAME));
+        assert_eq!(reported.step, "II");
+        assert_eq!((reported.minutes, reported.episodes), (1, 1));
+        let rhythm: Rhythm =
+            serde_json::from_slice(&std::fs::read(&reported.output).unwrap()).unwrap();
+        assert_eq!(rhythm.episodes[0].kind, EpisodeKind::Bradycardia);
+        assert_eq!(rhythm.minutes[0].beats, 30);
+    }
+}